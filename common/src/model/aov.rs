@@ -0,0 +1,298 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use nannou::image::{ImageResult, Rgba, RgbaImage};
+
+use super::texture::srgb_encode;
+
+/// Arbitrary output variable, a per pixel quantity written besides the color.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Aov {
+    /// Depth along the view axis of the camera.
+    Depth,
+    /// World space normal.
+    Normal,
+    Albedo,
+    /// 1 + index of the object, 0 where nothing was hit.
+    ObjectId,
+    Uv,
+    /// Screen movement in pixels of the visible point, from the previous
+    /// frame or across the shutter interval.
+    Motion,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 6] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::ObjectId,
+        Aov::Uv,
+        Aov::Motion,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "id",
+            Aov::Uv => "uv",
+            Aov::Motion => "motion",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        Aov::ALL.into_iter().find(|a| a.name() == name)
+    }
+
+    /// Channel names, a layer `name` is stored as `name.channel` in EXR files.
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal => &["X", "Y", "Z"],
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::ObjectId => &["id"],
+            Aov::Uv => &["U", "V"],
+            Aov::Motion => &["X", "Y"],
+        }
+    }
+
+    /// Parses a comma separated list of names, `all` selects every AOV.
+    pub fn parse_list(list: &str) -> Result<Vec<Aov>, String> {
+        if list == "all" {
+            return Ok(Aov::ALL.to_vec());
+        }
+        list.split(',')
+            .map(|name| Aov::from_name(name.trim()).ok_or(format!("unknown AOV {}", name)))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Layer {
+    aov: Aov,
+    /// Row major, channels interleaved.
+    data: Vec<f32>,
+}
+
+/// Named buffers of the AOVs a renderer was asked for.
+#[derive(Debug, Clone)]
+pub struct AovBuffers {
+    pub width: usize,
+    pub height: usize,
+    layers: Vec<Layer>,
+}
+
+impl AovBuffers {
+    pub fn new(width: usize, height: usize, aovs: &[Aov]) -> AovBuffers {
+        let mut layers: Vec<Layer> = vec![];
+        for aov in aovs {
+            if layers.iter().all(|l| l.aov != *aov) {
+                layers.push(Layer {
+                    aov: *aov,
+                    data: vec![0.; width * height * aov.channels().len()],
+                });
+            }
+        }
+
+        AovBuffers {
+            width,
+            height,
+            layers,
+        }
+    }
+
+    pub fn aovs(&self) -> Vec<Aov> {
+        self.layers.iter().map(|l| l.aov).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    pub fn wants(&self, aov: Aov) -> bool {
+        self.layers.iter().any(|l| l.aov == aov)
+    }
+
+    pub fn clear(&mut self) {
+        for layer in self.layers.iter_mut() {
+            layer.data.fill(0.);
+        }
+    }
+
+    /// Values of pixel `(x, y)`, ignored unless the AOV was asked for.
+    pub fn set(&mut self, aov: Aov, x: usize, y: usize, value: &[f32]) {
+        let width = self.width;
+        if let Some(layer) = self.layers.iter_mut().find(|l| l.aov == aov) {
+            let n = aov.channels().len();
+            let i = (y * width + x) * n;
+            layer.data[i..i + n].copy_from_slice(&value[..n]);
+        }
+    }
+
+    pub fn get(&self, aov: Aov, x: usize, y: usize) -> Option<&[f32]> {
+        let layer = self.layers.iter().find(|l| l.aov == aov)?;
+        let n = aov.channels().len();
+        let i = (y * self.width + x) * n;
+        Some(&layer.data[i..i + n])
+    }
+
+    /// Viewable picture of an AOV, depth is normalized to the farthest point
+    /// and motion to the longest vector.
+    pub fn to_image(&self, aov: Aov) -> Option<RgbaImage> {
+        let layer = self.layers.iter().find(|l| l.aov == aov)?;
+        let n = aov.channels().len();
+        let max = match aov {
+            Aov::Depth => layer.data.iter().fold(0f32, |m, d| m.max(*d)),
+            Aov::Motion => layer
+                .data
+                .chunks(2)
+                .fold(0f32, |m, v| m.max(v[0].hypot(v[1]))),
+            _ => 1.,
+        };
+
+        Some(RgbaImage::from_fn(
+            self.width as u32,
+            self.height as u32,
+            |x, y| {
+                let i = (y as usize * self.width + x as usize) * n;
+                let v = &layer.data[i..i + n];
+                let c = match aov {
+                    Aov::Depth if v[0] > 0. => [1. - v[0] / max; 3],
+                    Aov::Depth => [0.; 3],
+                    Aov::Normal => [0, 1, 2].map(|k| v[k] * 0.5 + 0.5),
+                    Aov::Albedo => [0, 1, 2].map(|k| srgb_encode(v[k])),
+                    Aov::ObjectId => id_color(v[0] as u32),
+                    Aov::Uv => [v[0].rem_euclid(1.), v[1].rem_euclid(1.), 0.],
+                    Aov::Motion => {
+                        let s = 0.5 / max.max(1e-6);
+                        [0.5 + v[0] * s, 0.5 + v[1] * s, 0.5]
+                    }
+                };
+                let [r, g, b] = c.map(|v| (v.clamp(0., 1.) * 255. + 0.5) as u8);
+                Rgba([r, g, b, 255])
+            },
+        ))
+    }
+
+    /// Writes each AOV to its own image, `frame.png` gives `frame.depth.png`,
+    /// `frame.normal.png` and so on.
+    pub fn save_images<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        let path = path.as_ref();
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("frame");
+        let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("png");
+
+        for layer in self.layers.iter() {
+            if let Some(image) = self.to_image(layer.aov) {
+                image.save(path.with_file_name(format!(
+                    "{}.{}.{}",
+                    stem,
+                    layer.aov.name(),
+                    ext
+                )))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes an uncompressed multi-layer OpenEXR file with the linear
+    /// `beauty` color, if any, as R, G, B and every AOV as its own layer.
+    pub fn save_exr<P: AsRef<Path>>(&self, path: P, beauty: Option<&[[f32; 3]]>) -> io::Result<()> {
+        // (name, interleaved data, channel count, channel index)
+        let mut channels: Vec<(String, &[f32], usize, usize)> = vec![];
+        if let Some(beauty) = beauty {
+            assert_eq!(beauty.len(), self.width * self.height);
+            for (k, name) in ["R", "G", "B"].into_iter().enumerate() {
+                channels.push((name.to_string(), beauty.as_flattened(), 3, k));
+            }
+        }
+        for layer in self.layers.iter() {
+            let names = layer.aov.channels();
+            for (k, name) in names.iter().enumerate() {
+                let full = format!("{}.{}", layer.aov.name(), name);
+                channels.push((full, &layer.data, names.len(), k));
+            }
+        }
+        // readers expect the channels sorted by name
+        channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut header = vec![];
+        let mut chlist = vec![];
+        for (name, ..) in channels.iter() {
+            chlist.extend_from_slice(name.as_bytes());
+            chlist.push(0);
+            chlist.extend_from_slice(&2i32.to_le_bytes()); // FLOAT
+            chlist.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+            chlist.extend_from_slice(&1i32.to_le_bytes());
+            chlist.extend_from_slice(&1i32.to_le_bytes());
+        }
+        chlist.push(0);
+
+        let window: Vec<u8> = [0, 0, self.width as i32 - 1, self.height as i32 - 1]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        attribute(&mut header, "channels", "chlist", &chlist);
+        attribute(&mut header, "compression", "compression", &[0]);
+        attribute(&mut header, "dataWindow", "box2i", &window);
+        attribute(&mut header, "displayWindow", "box2i", &window);
+        attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        attribute(
+            &mut header,
+            "pixelAspectRatio",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        attribute(
+            &mut header,
+            "screenWindowWidth",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        header.push(0);
+
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(&[0x76, 0x2f, 0x31, 0x01])?;
+        out.write_all(&2i32.to_le_bytes())?;
+        out.write_all(&header)?;
+
+        // one scanline per chunk
+        let line = self.width * 4 * channels.len();
+        let start = 8 + header.len() + 8 * self.height;
+        for y in 0..self.height {
+            out.write_all(&((start + y * (8 + line)) as u64).to_le_bytes())?;
+        }
+
+        for y in 0..self.height {
+            out.write_all(&(y as i32).to_le_bytes())?;
+            out.write_all(&(line as i32).to_le_bytes())?;
+            for (_, data, n, k) in channels.iter() {
+                for x in 0..self.width {
+                    out.write_all(&data[(y * self.width + x) * n + k].to_le_bytes())?;
+                }
+            }
+        }
+        out.flush()
+    }
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// Distinct color per object id, black for no object.
+fn id_color(id: u32) -> [f32; 3] {
+    if id == 0 {
+        return [0.; 3];
+    }
+    let h = id.wrapping_mul(0x9e3779b9);
+    [h >> 24, (h >> 16) & 0xff, (h >> 8) & 0xff].map(|c| 0.25 + 0.75 * c as f32 / 255.)
+}
@@ -0,0 +1,257 @@
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use nannou::image::codecs::hdr::HdrDecoder;
+use nannou::image::ImageResult;
+
+use super::mat::unit;
+
+/// Radiance arriving from infinitely far away, seen by rays leaving the scene.
+#[derive(Debug, Clone)]
+pub enum Environment {
+    Constant([f32; 3]),
+    /// Blends from `horizon` up to `zenith`, and to `ground` below the horizon.
+    Gradient {
+        zenith: [f32; 3],
+        horizon: [f32; 3],
+        ground: [f32; 3],
+    },
+    Map(Arc<EnvMap>),
+}
+
+impl Environment {
+    /// Default daylight sky.
+    pub fn sky() -> Environment {
+        Environment::Gradient {
+            zenith: [0.25, 0.45, 0.9],
+            horizon: [0.8, 0.85, 0.95],
+            ground: [0.25, 0.22, 0.2],
+        }
+    }
+
+    pub fn is_black(&self) -> bool {
+        matches!(self, Environment::Constant(c) if *c == [0., 0., 0.])
+    }
+
+    /// Radiance arriving along `-dir`, `dir` pointing away from the scene.
+    pub fn radiance(&self, dir: [f32; 3]) -> [f32; 3] {
+        match self {
+            Environment::Constant(c) => *c,
+            Environment::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => {
+                let y = dir[1];
+                if y >= 0.0 {
+                    lerp(*horizon, *zenith, y.sqrt())
+                } else {
+                    lerp(*horizon, *ground, (-y * 4.).min(1.))
+                }
+            }
+            Environment::Map(map) => map.radiance(dir),
+        }
+    }
+
+    /// Direction towards the environment chosen for `u` in [0, 1)^2, its
+    /// radiance and solid angle density.
+    pub fn sample(&self, u: [f32; 2]) -> ([f32; 3], [f32; 3], f32) {
+        match self {
+            Environment::Map(map) => map.sample(u),
+            _ => {
+                let y = 1. - 2. * u[0];
+                let r = (1. - y * y).max(0.).sqrt();
+                let phi = 2. * PI * u[1];
+                let dir = [r * phi.cos(), y, r * phi.sin()];
+                (dir, self.radiance(dir), 1. / (4. * PI))
+            }
+        }
+    }
+
+    /// Density of `sample` returning `dir`.
+    pub fn pdf(&self, dir: [f32; 3]) -> f32 {
+        match self {
+            Environment::Map(map) => map.pdf(dir),
+            _ => 1. / (4. * PI),
+        }
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Environment {
+        Environment::Constant([0., 0., 0.])
+    }
+}
+
+/// Piecewise constant density over [0, 1) with one bucket per value.
+#[derive(Debug, Clone)]
+struct Distribution {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution {
+    fn new(func: Vec<f32>) -> Distribution {
+        let n = func.len() as f32;
+        let mut cdf = vec![0.; func.len() + 1];
+        for i in 0..func.len() {
+            cdf[i + 1] = cdf[i] + func[i] / n;
+        }
+
+        let integral = cdf[func.len()];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0. {
+                *c / integral
+            } else {
+                i as f32 / n
+            };
+        }
+
+        Distribution {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    /// Position in [0, 1), its density and bucket.
+    fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let i = self
+            .cdf
+            .partition_point(|c| *c <= u)
+            .clamp(1, self.func.len())
+            - 1;
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let du = if width > 0. {
+            (u - self.cdf[i]) / width
+        } else {
+            0.
+        };
+
+        let x = (i as f32 + du) / self.func.len() as f32;
+        (x.min(0.99999), self.pdf(i), i)
+    }
+
+    fn pdf(&self, i: usize) -> f32 {
+        if self.integral > 0. {
+            self.func[i] / self.integral
+        } else {
+            1.
+        }
+    }
+}
+
+/// Equirectangular radiance map: u runs around the y axis starting at -z,
+/// v from the zenith (top row) to the nadir.
+#[derive(Debug, Clone)]
+pub struct EnvMap {
+    pub width: usize,
+    pub height: usize,
+    data: Vec<[f32; 3]>,
+    /// Row densities, weighted by the solid angle of each row.
+    marginal: Distribution,
+    /// Texel densities within each row.
+    rows: Vec<Distribution>,
+}
+
+impl EnvMap {
+    /// Map from row major linear radiance values.
+    pub fn new(width: usize, height: usize, data: Vec<[f32; 3]>) -> EnvMap {
+        assert_eq!(data.len(), width * height);
+
+        let rows: Vec<Distribution> = (0..height)
+            .map(|y| {
+                let sin = ((y as f32 + 0.5) / height as f32 * PI).sin();
+                Distribution::new(
+                    data[y * width..(y + 1) * width]
+                        .iter()
+                        .map(|c| luminance(*c) * sin)
+                        .collect(),
+                )
+            })
+            .collect();
+        let marginal = Distribution::new(rows.iter().map(|r| r.integral).collect());
+
+        EnvMap {
+            width,
+            height,
+            data,
+            marginal,
+            rows,
+        }
+    }
+
+    /// Loads a Radiance `.hdr` file.
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<EnvMap> {
+        let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+        let meta = decoder.metadata();
+        let data = decoder.read_image_hdr()?.into_iter().map(|p| p.0).collect();
+
+        Ok(EnvMap::new(meta.width as usize, meta.height as usize, data))
+    }
+
+    pub fn radiance(&self, dir: [f32; 3]) -> [f32; 3] {
+        let [u, v] = to_uv(dir);
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.data[y * self.width + x]
+    }
+
+    /// Picks a direction proportionally to the radiance of the map.
+    pub fn sample(&self, u: [f32; 2]) -> ([f32; 3], [f32; 3], f32) {
+        let (v, pdf_v, y) = self.marginal.sample(u[0]);
+        let (u, pdf_u, x) = self.rows[y].sample(u[1]);
+
+        let dir = from_uv([u, v]);
+        let sin = (v * PI).sin();
+        let pdf = if sin > 0. {
+            pdf_u * pdf_v / (2. * PI * PI * sin)
+        } else {
+            0.
+        };
+
+        (dir, self.data[y * self.width + x], pdf)
+    }
+
+    pub fn pdf(&self, dir: [f32; 3]) -> f32 {
+        let [u, v] = to_uv(dir);
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+
+        let sin = (v * PI).sin();
+        if sin <= 0. {
+            return 0.;
+        }
+        self.marginal.pdf(y) * self.rows[y].pdf(x) / (2. * PI * PI * sin)
+    }
+}
+
+fn to_uv(dir: [f32; 3]) -> [f32; 2] {
+    let d = unit(dir);
+    [
+        0.5 + d[0].atan2(-d[2]) / (2. * PI),
+        d[1].clamp(-1., 1.).acos() / PI,
+    ]
+}
+
+fn from_uv(uv: [f32; 2]) -> [f32; 3] {
+    let phi = (uv[0] - 0.5) * 2. * PI;
+    let theta = uv[1] * PI;
+    [
+        theta.sin() * phi.sin(),
+        theta.cos(),
+        -theta.sin() * phi.cos(),
+    ]
+}
+
+fn luminance(c: [f32; 3]) -> f32 {
+    0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2]
+}
+
+fn lerp(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)
+}
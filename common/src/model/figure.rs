@@ -1,4 +1,4 @@
-use std::{collections::HashMap, iter::Map, ops::Sub};
+use std::{collections::HashMap, iter::Map, ops::Index, ops::Sub};
 
 use nannou::{
     color::{rgb, BLACK, BLUE, GREEN},
@@ -7,6 +7,7 @@ use nannou::{
 };
 
 use super::mat::{cross, diff, dot, unit, Mat4x4};
+use super::material::Material;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Vertex {
@@ -62,6 +63,10 @@ pub struct Face {
     pub vertexes: [usize; 3],
     pub normal_vertex: i32,
     pub center_vertex: i32,
+    /// Texture coordinates of each corner, if the face is mapped.
+    pub uvs: Option<[[f32; 2]; 3]>,
+    /// Linear color of each corner, multiplying the albedo across the face.
+    pub colors: Option<[[f32; 3]; 3]>,
 }
 
 
@@ -71,6 +76,22 @@ impl Face {
             vertexes: v,
             normal_vertex: -1,
             center_vertex: -1,
+            uvs: None,
+            colors: None,
+        }
+    }
+
+    pub fn with_uvs(self, uvs: [[f32; 2]; 3]) -> Face {
+        Face {
+            uvs: Some(uvs),
+            ..self
+        }
+    }
+
+    pub fn with_colors(self, colors: [[f32; 3]; 3]) -> Face {
+        Face {
+            colors: Some(colors),
+            ..self
         }
     }
 
@@ -144,6 +165,29 @@ pub struct Obj3D {
     pub vertexes: Vec<Vertex>,
     pub edges: Vec<Edge>,
     pub faces: Vec<Face>,
+    pub material: Material,
+}
+
+/// Placement of a shared `Obj3D`, drawn without copying its vertexes.
+#[derive(Debug, Copy, Clone)]
+pub struct Instance {
+    /// Object to world.
+    pub transform: Mat4x4,
+    /// Multiplies the albedo of the object.
+    pub color: [f32; 3],
+}
+
+impl Instance {
+    pub fn new(transform: Mat4x4) -> Instance {
+        Instance {
+            transform,
+            color: [1., 1., 1.],
+        }
+    }
+
+    pub fn with_color(self, color: [f32; 3]) -> Instance {
+        Instance { color, ..self }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -151,16 +195,109 @@ pub struct Obj2D {
     pub points: Vec<Point2>,
     pub edges: Vec<Edge>,
     pub faces: Vec<Face>,
+    pub material: Material,
+}
+
+/// Objects by name, iterated in the order they were inserted so drawing
+/// and id assignment do not depend on hashing.
+#[derive(Debug, Clone)]
+pub struct ObjectMap<T> {
+    entries: Vec<(String, T)>,
+    index: HashMap<String, usize>,
+}
+
+impl<T> ObjectMap<T> {
+    pub fn new() -> ObjectMap<T> {
+        ObjectMap {
+            entries: vec![],
+            index: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.index.contains_key(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&T> {
+        self.index.get(name).map(|i| &self.entries[*i].1)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut T> {
+        self.index.get(name).map(|i| &mut self.entries[*i].1)
+    }
+
+    /// Replaces an object of the same name in place, or appends a new one.
+    pub fn insert(&mut self, name: String, value: T) -> Option<T> {
+        match self.index.get(&name) {
+            Some(i) => Some(std::mem::replace(&mut self.entries[*i].1, value)),
+            None => {
+                self.index.insert(name.clone(), self.entries.len());
+                self.entries.push((name, value));
+                None
+            }
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.entries.iter().map(|(_, v)| v)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.entries.iter_mut().map(|(_, v)| v)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &T)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut T)> {
+        self.entries.iter_mut().map(|(k, v)| (&*k, v))
+    }
+}
+
+impl<T> Default for ObjectMap<T> {
+    fn default() -> ObjectMap<T> {
+        ObjectMap::new()
+    }
+}
+
+impl<T> Index<&str> for ObjectMap<T> {
+    type Output = T;
+
+    fn index(&self, name: &str) -> &T {
+        self.get(name).expect("no object with that name")
+    }
+}
+
+impl<T> IntoIterator for ObjectMap<T> {
+    type Item = (String, T);
+    type IntoIter = std::vec::IntoIter<(String, T)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Mesh {
-    pub objects: HashMap<String, Obj3D>,
+    pub objects: ObjectMap<Obj3D>,
 }
 
 #[derive(Debug)]
 pub struct Screen {
-    pub objects: HashMap<String, Obj2D>,
+    pub objects: ObjectMap<Obj2D>,
 }
 
 impl Screen {
@@ -181,39 +318,6 @@ impl Screen {
             }
         }
     }
-
-    pub fn draw_faces(&self, draw: &Draw) {
-        for (k, v) in self.objects.iter() {
-            for face in v.faces.as_slice() {
-                let mut points = vec![] as Vec<Point2>;
-
-                for vt in face.vertexes.as_slice() {
-                    points.push(v.points[*vt])
-                }
-
-                points.push(pt2(
-                    v.points[face.vertexes[0]].x,
-                    v.points[face.vertexes[0]].y,
-                ));
-
-                draw.polyline()
-                    .stroke_weight(1.)
-                    .color(BLACK)
-                    .points(points);
-/* 
-                let last = v.points.len() - 1;
-
-                draw.line().stroke_weight(1.).color(GREEN).points(
-                    pt2(
-                        v.points[face.center_vertex as usize].x,
-                        v.points[face.center_vertex as usize].y,
-                    ),
-                    pt2(v.points[last].x, v.points[last].y),
-                );
-*/
-            }
-        }
-    }
 }
 
 impl From<[f32; 4]> for Vertex {
@@ -230,7 +334,7 @@ impl From<[f32; 4]> for Vertex {
 impl Mesh {
     pub fn new() -> Mesh {
         Mesh {
-            objects: HashMap::new(),
+            objects: ObjectMap::new(),
         }
     }
 
@@ -253,7 +357,7 @@ impl Mesh {
 
     pub fn to_screen(&mut self, x_size: f32, y_size: f32) -> Screen {
         let mut scr = Screen {
-            objects: HashMap::new(),
+            objects: ObjectMap::new(),
         };
 
         for (k, v) in self.objects.iter_mut() {
@@ -270,6 +374,7 @@ impl Mesh {
                     points: nv,
                     edges: v.edges.clone(),
                     faces: v.faces.clone(),
+                    material: v.material.clone(),
                 },
             );
         }
@@ -290,8 +395,31 @@ impl Obj3D {
             vertexes: vec![],
             faces: vec![],
             edges: vec![],
+            material: Material::default(),
+        }
+    }
+
+    /// Moves every vertex by `m` and recomputes the face center and normal
+    /// helper vertexes, which would otherwise be skewed by non uniform scales.
+    pub fn transform(&mut self, m: &Mat4x4) {
+        for v in self.vertexes.iter_mut() {
+            *v = Vertex::from_vec(m.mul_point(v.to_vec_3()));
+        }
+
+        for face in self.faces.iter() {
+            if face.center_vertex < 0 || face.normal_vertex < 0 {
+                continue;
+            }
+            let (normal, center) = face.normal(&self.vertexes);
+            self.vertexes[face.center_vertex as usize] = Vertex::from_vec(center);
+            self.vertexes[face.normal_vertex as usize] = Vertex::from_vec(normal);
         }
     }
+
+    pub fn set_material(&mut self, material: Material) -> &Obj3D {
+        self.material = material;
+        self
+    }
     pub fn push_face(&mut self, face: Face) -> &Obj3D {
         let last_v_idx = self.vertexes.len() - 1;
 
@@ -339,4 +467,41 @@ impl Obj3D {
         }
         self
     }
+
+    /// Draws the edges of every instance, and their text at the end point,
+    /// projecting the vertexes with `view_proj` times the instance transform
+    /// into a buffer shared by all instances. Edges reaching behind the eye
+    /// are left out.
+    pub fn draw_instance_lines(
+        &self,
+        draw: &Draw,
+        instances: &[Instance],
+        view_proj: Mat4x4,
+        x_size: f32,
+        y_size: f32,
+    ) {
+        let mut points: Vec<Option<Point2>> = Vec::with_capacity(self.vertexes.len());
+        for instance in instances {
+            let m = view_proj * instance.transform;
+            points.clear();
+            points.extend(self.vertexes.iter().map(|v| {
+                let p = Vertex::from(m * v.to_vec());
+                (p.w > 0.).then(|| p.screen(x_size, y_size))
+            }));
+
+            for edge in self.edges.iter() {
+                if let (Some(from), Some(to)) = (points[edge.from], points[edge.to]) {
+                    draw.line()
+                        .stroke_weight(1.)
+                        .color(edge.color)
+                        .points(from, to);
+                    if let Some(txt) = &edge.text {
+                        draw.text(txt.as_str())
+                            .xy(pt2(to.x, to.y + 10.0))
+                            .color(edge.color);
+                    }
+                }
+            }
+        }
+    }
 }
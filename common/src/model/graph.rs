@@ -0,0 +1,215 @@
+use std::sync::Arc;
+
+use super::figure::{Instance, Mesh, Obj3D};
+use super::mat::Mat4x4;
+
+/// Node of a `SceneGraph`, placed relative to its parent.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub name: String,
+    pub parent: Option<usize>,
+    /// In insertion order.
+    pub children: Vec<usize>,
+    /// Shared object drawn at the node, an index of `SceneGraph::objects`.
+    pub object: Option<usize>,
+    /// Multiplies the albedo of the object at this node only.
+    pub color: [f32; 3],
+    local: Mat4x4,
+    world: Mat4x4,
+    /// Set when `local` changed since the world transform was computed.
+    dirty: bool,
+}
+
+impl Node {
+    pub fn local(&self) -> Mat4x4 {
+        self.local
+    }
+
+    /// Local transform composed with those of all ancestors, as of the last
+    /// `SceneGraph::update`.
+    pub fn world(&self) -> Mat4x4 {
+        self.world
+    }
+}
+
+/// Hierarchy of transforms. Objects are stored once and can be placed under
+/// any number of nodes. Nodes are visited in the order they were added.
+#[derive(Debug, Clone)]
+pub struct SceneGraph {
+    nodes: Vec<Node>,
+    roots: Vec<usize>,
+    objects: Vec<Arc<Obj3D>>,
+}
+
+impl SceneGraph {
+    pub fn new() -> SceneGraph {
+        SceneGraph {
+            nodes: vec![],
+            roots: vec![],
+            objects: vec![],
+        }
+    }
+
+    /// Stores an object in model space, returns its index.
+    pub fn push_object(&mut self, obj: Obj3D) -> usize {
+        self.objects.push(Arc::new(obj));
+        self.objects.len() - 1
+    }
+
+    pub fn object(&self, id: usize) -> &Obj3D {
+        &self.objects[id]
+    }
+
+    pub fn objects(&self) -> &[Arc<Obj3D>] {
+        &self.objects
+    }
+
+    /// The object for editing, copied first if a clone of the graph shares it.
+    pub fn object_mut(&mut self, id: usize) -> &mut Obj3D {
+        Arc::make_mut(&mut self.objects[id])
+    }
+
+    /// Adds a node below `parent`, or a root without one, and returns its index.
+    pub fn push_node(
+        &mut self,
+        name: &str,
+        parent: Option<usize>,
+        local: Mat4x4,
+        object: Option<usize>,
+    ) -> usize {
+        let id = self.nodes.len();
+        let world = match parent {
+            Some(p) => self.nodes[p].world * local,
+            None => local,
+        };
+        self.nodes.push(Node {
+            name: String::from(name),
+            parent,
+            children: vec![],
+            object,
+            color: [1., 1., 1.],
+            local,
+            world,
+            // a dirty parent updates its new child too
+            dirty: false,
+        });
+
+        match parent {
+            Some(p) => self.nodes[p].children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    pub fn node(&self, id: usize) -> &Node {
+        &self.nodes[id]
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// First node called `name` in traversal order.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.traverse()
+            .into_iter()
+            .find(|id| self.nodes[*id].name == name)
+    }
+
+    /// Replaces the local transform, the world transforms of the node and its
+    /// descendants are recomputed by the next `update`.
+    pub fn set_local(&mut self, id: usize, local: Mat4x4) {
+        self.nodes[id].local = local;
+        self.nodes[id].dirty = true;
+    }
+
+    pub fn set_color(&mut self, id: usize, color: [f32; 3]) {
+        self.nodes[id].color = color;
+    }
+
+    /// Recomputes the world transforms of the dirty nodes and of everything
+    /// below them.
+    pub fn update(&mut self) {
+        let mut stack: Vec<(usize, bool)> = self.roots.iter().rev().map(|r| (*r, false)).collect();
+        while let Some((id, parent_dirty)) = stack.pop() {
+            let dirty = parent_dirty || self.nodes[id].dirty;
+            if dirty {
+                let local = self.nodes[id].local;
+                self.nodes[id].world = match self.nodes[id].parent {
+                    Some(p) => self.nodes[p].world * local,
+                    None => local,
+                };
+                self.nodes[id].dirty = false;
+            }
+            stack.extend(self.nodes[id].children.iter().rev().map(|c| (*c, dirty)));
+        }
+    }
+
+    /// Depth first node order, parents before children and siblings in the
+    /// order they were added.
+    pub fn traverse(&self) -> Vec<usize> {
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut stack: Vec<usize> = self.roots.iter().rev().copied().collect();
+        while let Some(id) = stack.pop() {
+            order.push(id);
+            stack.extend(self.nodes[id].children.iter().rev());
+        }
+        order
+    }
+
+    /// Slash separated names from the root down to the node.
+    pub fn path(&self, id: usize) -> String {
+        let node = &self.nodes[id];
+        match node.parent {
+            Some(p) => format!("{}/{}", self.path(p), node.name),
+            None => node.name.clone(),
+        }
+    }
+
+    /// Placements of every object, indexed like `objects`, in traversal order.
+    pub fn instances(&self) -> Vec<Vec<Instance>> {
+        let mut instances = vec![vec![]; self.objects.len()];
+        for id in self.traverse() {
+            let node = &self.nodes[id];
+            if let Some(object) = node.object {
+                instances[object].push(Instance::new(node.world).with_color(node.color));
+            }
+        }
+        instances
+    }
+
+    /// Copy of every placed object moved to world space, named by node path.
+    pub fn to_mesh(&self) -> Mesh {
+        let mut mesh = Mesh::new();
+        for id in self.traverse() {
+            if let Some(object) = self.nodes[id].object {
+                let mut obj = Obj3D::clone(&self.objects[object]);
+                obj.transform(&self.nodes[id].world);
+                mesh.objects.insert(self.path(id), obj);
+            }
+        }
+        mesh
+    }
+}
+
+impl Default for SceneGraph {
+    fn default() -> SceneGraph {
+        SceneGraph::new()
+    }
+}
+
+impl From<Mesh> for SceneGraph {
+    /// One root node per object, keeping the object names and placement.
+    fn from(mesh: Mesh) -> SceneGraph {
+        let mut graph = SceneGraph::new();
+        for (name, obj) in mesh.objects {
+            let object = graph.push_object(obj);
+            graph.push_node(&name, None, Mat4x4::unit(), Some(object));
+        }
+        graph
+    }
+}
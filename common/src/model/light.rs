@@ -0,0 +1,290 @@
+use std::f32::consts::PI;
+
+use serde::Deserialize;
+
+use super::mat::{add, basis, cross, diff, dot, mul, neg, unit};
+
+const EPSILON: f32 = 1e-4;
+
+/// Scene files name the variant in a `type` field, `{"type": "point", ...}`.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Light {
+    /// Parallel light travelling along `dir`.
+    Directional { dir: [f32; 3], radiance: [f32; 3] },
+    Point {
+        position: [f32; 3],
+        intensity: [f32; 3],
+    },
+    /// Parallelogram spanned by `u` and `v` from `corner`, emitting on the
+    /// side of `u x v`.
+    Rect {
+        corner: [f32; 3],
+        u: [f32; 3],
+        v: [f32; 3],
+        radiance: [f32; 3],
+    },
+    /// Disk emitting on the side of `normal`.
+    Disk {
+        center: [f32; 3],
+        normal: [f32; 3],
+        radius: f32,
+        radiance: [f32; 3],
+    },
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        radiance: [f32; 3],
+    },
+}
+
+/// Light reaching a point from one sampled direction.
+#[derive(Debug, Copy, Clone)]
+pub struct LightSample {
+    /// Unit direction towards the light.
+    pub wi: [f32; 3],
+    pub dist: f32,
+    pub radiance: [f32; 3],
+    /// Solid angle density of `wi`, 1 for delta lights.
+    pub pdf: f32,
+}
+
+impl Light {
+    /// Directional and point lights can only be reached by sampling them.
+    pub fn is_delta(&self) -> bool {
+        matches!(self, Light::Directional { .. } | Light::Point { .. })
+    }
+
+    /// Picks a point of the light as seen from `p`, `u` in [0, 1)^2. None
+    /// when `p` cannot see the emitting side.
+    pub fn sample(&self, p: [f32; 3], u: [f32; 2]) -> Option<LightSample> {
+        let on_surface = |q: [f32; 3], normal: [f32; 3], area: f32, radiance: [f32; 3]| {
+            let d = diff(q, p);
+            let dist2 = dot(d, d);
+            let wi = unit(d);
+            let cos = -dot(wi, normal);
+            (cos > 0.0).then(|| LightSample {
+                wi,
+                dist: dist2.sqrt(),
+                radiance,
+                pdf: dist2 / (cos * area),
+            })
+        };
+
+        match *self {
+            Light::Directional { dir, radiance } => Some(LightSample {
+                wi: neg(unit(dir)),
+                dist: f32::INFINITY,
+                radiance,
+                pdf: 1.,
+            }),
+            Light::Point {
+                position,
+                intensity,
+            } => {
+                let d = diff(position, p);
+                let dist2 = dot(d, d);
+                Some(LightSample {
+                    wi: unit(d),
+                    dist: dist2.sqrt(),
+                    radiance: mul(intensity, 1. / dist2),
+                    pdf: 1.,
+                })
+            }
+            Light::Rect {
+                corner,
+                u: eu,
+                v: ev,
+                radiance,
+            } => {
+                let n = cross(eu, ev);
+                let area = dot(n, n).sqrt();
+                let q = add(corner, add(mul(eu, u[0]), mul(ev, u[1])));
+                on_surface(q, unit(n), area, radiance)
+            }
+            Light::Disk {
+                center,
+                normal,
+                radius,
+                radiance,
+            } => {
+                let normal = unit(normal);
+                let (t, b) = basis(normal);
+                let r = radius * u[0].sqrt();
+                let phi = 2. * PI * u[1];
+                let q = add(center, add(mul(t, r * phi.cos()), mul(b, r * phi.sin())));
+                on_surface(q, normal, PI * radius * radius, radiance)
+            }
+            Light::Sphere {
+                center,
+                radius,
+                radiance,
+            } => {
+                // uniform over the cone the sphere subtends
+                let d = diff(center, p);
+                let dist2 = dot(d, d);
+                if dist2 <= radius * radius {
+                    return None;
+                }
+                let cos_max = (1. - radius * radius / dist2).sqrt();
+                let cos = 1. - u[0] * (1. - cos_max);
+                let sin = (1. - cos * cos).max(0.).sqrt();
+                let phi = 2. * PI * u[1];
+
+                let axis = unit(d);
+                let (t, b) = basis(axis);
+                let wi = unit(add(
+                    add(mul(t, sin * phi.cos()), mul(b, sin * phi.sin())),
+                    mul(axis, cos),
+                ));
+                let dist = sphere_hit(center, radius, p, wi, f32::INFINITY)
+                    .unwrap_or(dist2.sqrt() - radius);
+
+                Some(LightSample {
+                    wi,
+                    dist,
+                    radiance,
+                    pdf: 1. / (2. * PI * (1. - cos_max)),
+                })
+            }
+        }
+    }
+
+    /// Distance along the ray to the light surface, delta lights are never hit.
+    pub fn intersect(&self, origin: [f32; 3], dir: [f32; 3], t_max: f32) -> Option<f32> {
+        match *self {
+            Light::Directional { .. } | Light::Point { .. } => None,
+            Light::Rect { corner, u, v, .. } => {
+                let t = plane_hit(corner, cross(u, v), origin, dir, t_max)?;
+                let q = diff(add(origin, mul(dir, t)), corner);
+                // coordinates of q along u and v, which need not be orthogonal
+                let (uu, uv, vv) = (dot(u, u), dot(u, v), dot(v, v));
+                let (qu, qv) = (dot(q, u), dot(q, v));
+                let det = uu * vv - uv * uv;
+                if det <= 0. {
+                    return None;
+                }
+                let s = (vv * qu - uv * qv) / det;
+                let r = (uu * qv - uv * qu) / det;
+                ((0.0..=1.0).contains(&s) && (0.0..=1.0).contains(&r)).then_some(t)
+            }
+            Light::Disk {
+                center,
+                normal,
+                radius,
+                ..
+            } => {
+                let t = plane_hit(center, normal, origin, dir, t_max)?;
+                let q = diff(add(origin, mul(dir, t)), center);
+                (dot(q, q) <= radius * radius).then_some(t)
+            }
+            Light::Sphere { center, radius, .. } => sphere_hit(center, radius, origin, dir, t_max),
+        }
+    }
+
+    /// Radiance leaving the light towards a ray travelling along `dir`.
+    pub fn emitted(&self, dir: [f32; 3]) -> [f32; 3] {
+        match *self {
+            Light::Directional { .. } | Light::Point { .. } => [0., 0., 0.],
+            Light::Rect { u, v, radiance, .. } => facing(cross(u, v), dir, radiance),
+            Light::Disk {
+                normal, radiance, ..
+            } => facing(normal, dir, radiance),
+            Light::Sphere { radiance, .. } => radiance,
+        }
+    }
+
+    /// Solid angle density of `sample` choosing `wi` from `p`.
+    pub fn pdf(&self, p: [f32; 3], wi: [f32; 3]) -> f32 {
+        let t = match self.intersect(p, wi, f32::INFINITY) {
+            Some(t) => t,
+            None => return 0.,
+        };
+
+        match *self {
+            Light::Directional { .. } | Light::Point { .. } => 0.,
+            Light::Rect { u, v, .. } => {
+                let n = cross(u, v);
+                let area = dot(n, n).sqrt();
+                t * t / (dot(unit(n), wi).abs() * area)
+            }
+            Light::Disk { normal, radius, .. } => {
+                t * t / (dot(unit(normal), wi).abs() * PI * radius * radius)
+            }
+            Light::Sphere { center, radius, .. } => {
+                let d = diff(center, p);
+                let dist2 = dot(d, d);
+                if dist2 <= radius * radius {
+                    return 0.;
+                }
+                let cos_max = (1. - radius * radius / dist2).sqrt();
+                1. / (2. * PI * (1. - cos_max))
+            }
+        }
+    }
+}
+
+fn facing(normal: [f32; 3], dir: [f32; 3], radiance: [f32; 3]) -> [f32; 3] {
+    if dot(normal, dir) < 0.0 {
+        radiance
+    } else {
+        [0., 0., 0.]
+    }
+}
+
+fn plane_hit(
+    point: [f32; 3],
+    normal: [f32; 3],
+    origin: [f32; 3],
+    dir: [f32; 3],
+    t_max: f32,
+) -> Option<f32> {
+    let denom = dot(dir, normal);
+    if denom.abs() < 1e-8 {
+        return None;
+    }
+    let t = dot(diff(point, origin), normal) / denom;
+    (t > EPSILON && t < t_max).then_some(t)
+}
+
+fn sphere_hit(
+    center: [f32; 3],
+    radius: f32,
+    origin: [f32; 3],
+    dir: [f32; 3],
+    t_max: f32,
+) -> Option<f32> {
+    let oc = diff(origin, center);
+    let b = dot(oc, dir);
+    let c = dot(oc, oc) - radius * radius;
+    let disc = b * b - c;
+    if disc < 0.0 {
+        return None;
+    }
+
+    let sq = disc.sqrt();
+    [-b - sq, -b + sq]
+        .into_iter()
+        .find(|t| *t > EPSILON && *t < t_max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rect_hits_follow_sheared_edges() {
+        let light = Light::Rect {
+            corner: [0., 0., 0.],
+            u: [1., 0., 0.],
+            v: [1., 1., 0.],
+            radiance: [1., 1., 1.],
+        };
+        let down = |x: f32, y: f32| light.intersect([x, y, 1.], [0., 0., -1.], f32::INFINITY);
+
+        // inside, though past u when projected on it
+        assert_eq!(down(1.4, 0.5), Some(1.));
+        // outside, though within u and v when projected on each
+        assert_eq!(down(0.4, 0.5), None);
+    }
+}
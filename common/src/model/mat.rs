@@ -61,6 +61,44 @@ impl Mat4x4 {
 
         [x, y, z]
     }
+
+    pub fn mul_point(&self, p: [f32; 3]) -> [f32; 3] {
+        let r = *self * [p[0], p[1], p[2], 1.];
+        [r[0], r[1], r[2]]
+    }
+
+    /// Gauss-Jordan inverse, None for a singular matrix.
+    pub fn inverse(&self) -> Option<Mat4x4> {
+        let mut a = self.mat;
+        let mut inv = Mat4x4::unit().mat;
+
+        for c in 0..4 {
+            let pivot = (c..4).max_by(|i, j| a[*i][c].abs().total_cmp(&a[*j][c].abs()))?;
+            if a[pivot][c].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(c, pivot);
+            inv.swap(c, pivot);
+
+            let k = 1. / a[c][c];
+            for i in 0..4 {
+                a[c][i] *= k;
+                inv[c][i] *= k;
+            }
+
+            for r in 0..4 {
+                if r != c {
+                    let f = a[r][c];
+                    for i in 0..4 {
+                        a[r][i] -= f * a[c][i];
+                        inv[r][i] -= f * inv[c][i];
+                    }
+                }
+            }
+        }
+
+        Some(Mat4x4 { mat: inv })
+    }
 }
 
 impl Mul<[f32; 4]> for Mat4x4 {
@@ -130,6 +168,7 @@ impl Mul<&Mesh> for Mat4x4 {
             obj.push_vertexes(nv);
             obj.push_edges(v.edges.clone());
             obj.push_faces(v.faces.clone());
+            obj.set_material(v.material.clone());
         }
         new_mash
     }
@@ -257,6 +296,17 @@ pub fn neg(l: [f32; 3]) -> [f32; 3] {
     [-l[0], -l[1], -l[2]]
 }
 
+/// Orthonormal tangent and bitangent for the unit vector `n`.
+pub fn basis(n: [f32; 3]) -> ([f32; 3], [f32; 3]) {
+    let a = if n[0].abs() > 0.9 {
+        [0., 1., 0.]
+    } else {
+        [1., 0., 0.]
+    };
+    let t = unit(cross(a, n));
+    (t, cross(n, t))
+}
+
 pub fn viewer(eye: [f32; 3], at: [f32; 3], up: [f32; 3]) -> Mat4x4 {
     let n = unit(diff(eye, at));
 
@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::texture::{Texture, TextureMap};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MaterialKind {
+    Lambertian,
+    Metal,
+    Dielectric,
+    Emissive,
+    /// glTF style metallic-roughness: a diffuse base blended with a GGX
+    /// specular lobe by `metallic`.
+    Pbr,
+}
+
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub kind: MaterialKind,
+    pub albedo: [f32; 3],
+    pub roughness: f32,
+    pub metallic: f32,
+    pub ior: f32,
+    pub emission: [f32; 3],
+    /// Multiplies `albedo` across the surface.
+    pub albedo_map: Option<TextureMap>,
+}
+
+impl Material {
+    pub fn lambertian(albedo: [f32; 3]) -> Material {
+        Material {
+            kind: MaterialKind::Lambertian,
+            albedo,
+            roughness: 1.,
+            metallic: 0.,
+            ior: 1.5,
+            emission: [0., 0., 0.],
+            albedo_map: None,
+        }
+    }
+
+    pub fn metal(albedo: [f32; 3], roughness: f32) -> Material {
+        Material {
+            kind: MaterialKind::Metal,
+            roughness,
+            metallic: 1.,
+            ..Material::lambertian(albedo)
+        }
+    }
+
+    pub fn dielectric(ior: f32) -> Material {
+        Material {
+            kind: MaterialKind::Dielectric,
+            roughness: 0.,
+            ior,
+            ..Material::lambertian([1., 1., 1.])
+        }
+    }
+
+    pub fn emissive(emission: [f32; 3]) -> Material {
+        Material {
+            kind: MaterialKind::Emissive,
+            emission,
+            ..Material::lambertian([0., 0., 0.])
+        }
+    }
+
+    pub fn pbr(albedo: [f32; 3], metallic: f32, roughness: f32) -> Material {
+        Material {
+            kind: MaterialKind::Pbr,
+            roughness,
+            metallic,
+            ..Material::lambertian(albedo)
+        }
+    }
+
+    pub fn with_emission(self, emission: [f32; 3]) -> Material {
+        Material { emission, ..self }
+    }
+
+    pub fn with_albedo_map(self, map: TextureMap) -> Material {
+        Material {
+            albedo_map: Some(map),
+            ..self
+        }
+    }
+
+    /// Albedo at a surface point, `footprint` is the pixel size in uv units.
+    pub fn albedo_at(&self, point: [f32; 3], uv: [f32; 2], footprint: f32) -> [f32; 3] {
+        match self.albedo_map.as_ref() {
+            Some(map) => {
+                let c = map.sample(point, uv, footprint);
+                [
+                    self.albedo[0] * c[0],
+                    self.albedo[1] * c[1],
+                    self.albedo[2] * c[2],
+                ]
+            }
+            None => self.albedo,
+        }
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission.iter().any(|e| *e > 0.0)
+    }
+
+    /// Material from the factors of a glTF `pbrMetallicRoughness` block.
+    pub fn from_gltf(
+        base_color: [f32; 4],
+        metallic: f32,
+        roughness: f32,
+        emissive: [f32; 3],
+        ior: f32,
+    ) -> Material {
+        let albedo = [base_color[0], base_color[1], base_color[2]];
+        let material = if base_color[3] < 1.0 {
+            Material {
+                albedo,
+                ..Material::dielectric(ior)
+            }
+        } else {
+            Material {
+                ior,
+                ..Material::pbr(albedo, metallic, roughness)
+            }
+        };
+        material.with_emission(emissive)
+    }
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material::lambertian([0.8, 0.8, 0.8])
+    }
+}
+
+/// Parses a Wavefront MTL library.
+///
+/// `Kd`, `Ke`, `Ni`, `Ns`, `d`/`Tr` and `illum` are honoured, as well as the
+/// PBR extension keys `Pr` and `Pm`. `map_Kd` images are loaded relative to
+/// `dir`.
+pub fn parse_mtl(src: &str, dir: &Path) -> io::Result<HashMap<String, Material>> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlEntry)> = None;
+
+    for line in src.lines() {
+        let mut tokens = line.split_whitespace();
+        let key = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        let values: Vec<f32> = tokens.clone().filter_map(|t| t.parse().ok()).collect();
+
+        if key == "newmtl" {
+            if let Some((name, entry)) = current.take() {
+                materials.insert(name, entry.material(dir)?);
+            }
+            let name = tokens.collect::<Vec<&str>>().join(" ");
+            current = Some((name, MtlEntry::default()));
+            continue;
+        }
+
+        let entry = match current.as_mut() {
+            Some((_, entry)) => entry,
+            None => continue,
+        };
+
+        match (key, values.as_slice()) {
+            ("Kd", [r, g, b, ..]) => entry.diffuse = [*r, *g, *b],
+            ("Ke", [r, g, b, ..]) => entry.emission = [*r, *g, *b],
+            ("Ni", [n, ..]) => entry.ior = *n,
+            ("Ns", [n, ..]) => entry.shininess = Some(*n),
+            ("d", [d, ..]) => entry.opacity = *d,
+            ("Tr", [t, ..]) => entry.opacity = 1. - *t,
+            ("Pr", [r, ..]) => entry.roughness = Some(*r),
+            ("Pm", [m, ..]) => entry.metallic = Some(*m),
+            ("illum", [i, ..]) => entry.illum = *i as u32,
+            // options such as -s or -o are not supported, the file name comes last
+            ("map_Kd", _) => entry.diffuse_map = line.split_whitespace().last().map(String::from),
+            _ => {}
+        }
+    }
+
+    if let Some((name, entry)) = current {
+        materials.insert(name, entry.material(dir)?);
+    }
+
+    Ok(materials)
+}
+
+pub fn load_mtl<P: AsRef<Path>>(path: P) -> io::Result<HashMap<String, Material>> {
+    let path = path.as_ref();
+    parse_mtl(
+        &fs::read_to_string(path)?,
+        path.parent().unwrap_or(Path::new(".")),
+    )
+}
+
+/// Reads the `materials` array of a glTF 2.0 JSON document, in declaration
+/// order. Unnamed materials are called `material<index>`.
+pub fn parse_gltf_materials(src: &str) -> Result<Vec<(String, Material)>, String> {
+    let doc: serde_json::Value = serde_json::from_str(src).map_err(|e| e.to_string())?;
+
+    let materials = match doc.get("materials").and_then(|m| m.as_array()) {
+        Some(m) => m,
+        None => return Ok(vec![]),
+    };
+
+    materials
+        .iter()
+        .enumerate()
+        .map(|(i, m)| {
+            let pbr = &m["pbrMetallicRoughness"];
+            let base = factor(&pbr["baseColorFactor"], [1., 1., 1., 1.])
+                .map_err(|e| format!("material {}: baseColorFactor {}", i, e))?;
+            let emissive = factor(&m["emissiveFactor"], [0., 0., 0.])
+                .map_err(|e| format!("material {}: emissiveFactor {}", i, e))?;
+            let strength = m["extensions"]["KHR_materials_emissive_strength"]["emissiveStrength"]
+                .as_f64()
+                .unwrap_or(1.) as f32;
+            let ior = m["extensions"]["KHR_materials_ior"]["ior"]
+                .as_f64()
+                .unwrap_or(1.5) as f32;
+
+            let name = m["name"]
+                .as_str()
+                .map(String::from)
+                .unwrap_or(format!("material{}", i));
+
+            let material = Material::from_gltf(
+                base,
+                pbr["metallicFactor"].as_f64().unwrap_or(1.) as f32,
+                pbr["roughnessFactor"].as_f64().unwrap_or(1.) as f32,
+                emissive.map(|e| e * strength),
+                ior,
+            );
+            Ok((name, material))
+        })
+        .collect()
+}
+
+pub fn load_gltf_materials<P: AsRef<Path>>(path: P) -> io::Result<Vec<(String, Material)>> {
+    parse_gltf_materials(&fs::read_to_string(path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// The `N` numbers of a glTF factor, `default` when it is missing.
+fn factor<const N: usize>(v: &serde_json::Value, default: [f32; N]) -> Result<[f32; N], String> {
+    if v.is_null() {
+        return Ok(default);
+    }
+
+    let invalid = || format!("needs {} numbers", N);
+    let values = v.as_array().filter(|a| a.len() == N).ok_or_else(invalid)?;
+    let mut factor = default;
+    for (f, x) in factor.iter_mut().zip(values) {
+        *f = x.as_f64().ok_or_else(invalid)? as f32;
+    }
+    Ok(factor)
+}
+
+struct MtlEntry {
+    diffuse: [f32; 3],
+    emission: [f32; 3],
+    ior: f32,
+    shininess: Option<f32>,
+    opacity: f32,
+    roughness: Option<f32>,
+    metallic: Option<f32>,
+    illum: u32,
+    diffuse_map: Option<String>,
+}
+
+impl Default for MtlEntry {
+    fn default() -> MtlEntry {
+        MtlEntry {
+            diffuse: [0.8, 0.8, 0.8],
+            emission: [0., 0., 0.],
+            ior: 1.5,
+            shininess: None,
+            opacity: 1.,
+            roughness: None,
+            metallic: None,
+            illum: 2,
+            diffuse_map: None,
+        }
+    }
+}
+
+impl MtlEntry {
+    fn material(&self, dir: &Path) -> io::Result<Material> {
+        // Phong exponent to an approximately equivalent GGX roughness
+        let roughness = self
+            .roughness
+            .or(self.shininess.map(|ns| (2. / (ns + 2.)).sqrt()))
+            .unwrap_or(1.);
+
+        let material = if self.opacity < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            Material::dielectric(self.ior)
+        } else if let Some(metallic) = self.metallic {
+            Material {
+                ior: self.ior,
+                ..Material::pbr(self.diffuse, metallic, roughness)
+            }
+        } else if self.illum == 3 {
+            Material::metal(self.diffuse, roughness)
+        } else if self.emission.iter().any(|e| *e > 0.0) && self.diffuse == [0., 0., 0.] {
+            Material::emissive(self.emission)
+        } else {
+            Material::lambertian(self.diffuse)
+        };
+
+        let material = material.with_emission(self.emission);
+
+        Ok(match self.diffuse_map.as_ref() {
+            Some(file) => {
+                let path = dir.join(file);
+                let texture = Texture::load(&path).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}: {}", path.display(), e),
+                    )
+                })?;
+                // as in the MTL spec, the map is multiplied by Kd
+                material.with_albedo_map(TextureMap::Image(Arc::new(texture)))
+            }
+            None => material,
+        })
+    }
+}
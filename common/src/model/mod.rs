@@ -1,2 +1,15 @@
+pub mod aov;
+pub mod environment;
 pub mod figure;
-pub mod mat;
\ No newline at end of file
+pub mod graph;
+pub mod light;
+pub mod mat;
+pub mod primitives;
+pub mod procedural;
+pub mod material;
+pub mod raster;
+pub mod rng;
+pub mod scene;
+pub mod texture;
+pub mod transform;
+pub mod wavefront;
@@ -0,0 +1,289 @@
+//! Triangle meshes of common shapes, centered on the origin with y up.
+//!
+//! Faces are counter clockwise seen from outside so their normals point
+//! outwards, and every corner has a uv with v growing upwards. Curved
+//! shapes are cut into `segments` around the y axis.
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use super::figure::{Face, Obj3D, Vertex};
+use super::mat::{add, cross, diff, dot, mul, unit};
+
+/// Box of `size` along x, y and z, every side split into `segments` squared
+/// quads mapped to the whole texture.
+pub fn cuboid(size: [f32; 3], segments: usize) -> Obj3D {
+    let [x, y, z] = mul(size, 0.5);
+    let mut b = Builder::default();
+    // corner at uv (0, 0), then the directions of u and v
+    for (origin, u, v) in [
+        ([-x, -y, z], [2. * x, 0., 0.], [0., 2. * y, 0.]),
+        ([x, -y, -z], [-2. * x, 0., 0.], [0., 2. * y, 0.]),
+        ([x, -y, z], [0., 0., -2. * z], [0., 2. * y, 0.]),
+        ([-x, -y, -z], [0., 0., 2. * z], [0., 2. * y, 0.]),
+        ([-x, y, z], [2. * x, 0., 0.], [0., 0., -2. * z]),
+        ([-x, -y, -z], [2. * x, 0., 0.], [0., 0., 2. * z]),
+    ] {
+        b.grid(segments.max(1), segments.max(1), |s, t| {
+            (add(origin, add(mul(u, s), mul(v, t))), [s, t])
+        });
+    }
+    b.build()
+}
+
+/// Square of `size` in the y = 0 plane facing up, split in a grid of
+/// `segments` quads by `segments`. v grows towards -z.
+pub fn plane(size: f32, segments: usize) -> Obj3D {
+    let mut b = Builder::default();
+    b.grid(segments.max(1), segments.max(1), |s, t| {
+        ([size * (s - 0.5), 0., size * (0.5 - t)], [s, t])
+    });
+    b.build()
+}
+
+/// Sphere of longitude `segments` by latitude `rings`, u follows the
+/// longitude and v the latitude.
+pub fn uv_sphere(radius: f32, segments: usize, rings: usize) -> Obj3D {
+    let rings = rings.max(2);
+    let profile: Vec<[f32; 2]> = (0..=rings)
+        .map(|i| {
+            let theta = PI * (1. - i as f32 / rings as f32);
+            [radius * theta.sin(), radius * theta.cos()]
+        })
+        .collect();
+    lathe(&profile, segments)
+}
+
+/// Sphere made by splitting every face of an icosahedron in four
+/// `subdivisions` times, with faces of nearly equal size. Uvs are the
+/// longitude and latitude of the corners.
+pub fn icosphere(radius: f32, subdivisions: usize) -> Obj3D {
+    let t = (1. + 5f32.sqrt()) * 0.5;
+    let mut points: Vec<[f32; 3]> = [
+        [-1., t, 0.],
+        [1., t, 0.],
+        [-1., -t, 0.],
+        [1., -t, 0.],
+        [0., -1., t],
+        [0., 1., t],
+        [0., -1., -t],
+        [0., 1., -t],
+        [t, 0., -1.],
+        [t, 0., 1.],
+        [-t, 0., -1.],
+        [-t, 0., 1.],
+    ]
+    .into_iter()
+    .map(unit)
+    .collect();
+    let mut triangles: Vec<[usize; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        // edges are shared by two faces which must reuse the same midpoint
+        let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut midpoint = |a: usize, b: usize| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                points.push(unit(mul(add(points[a], points[b]), 0.5)));
+                points.len() - 1
+            })
+        };
+        triangles = triangles
+            .iter()
+            .flat_map(|[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(*a, *b), midpoint(*b, *c), midpoint(*c, *a));
+                [[*a, ab, ca], [*b, bc, ab], [*c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    let mut b = Builder::default();
+    for p in points.iter() {
+        b.vertex(mul(*p, radius));
+    }
+    for [p0, p1, p2] in triangles {
+        let [mut uv0, mut uv1, mut uv2] = [p0, p1, p2].map(|i| {
+            let p = points[i];
+            [
+                0.5 + p[0].atan2(p[2]) / (2. * PI),
+                0.5 + p[1].clamp(-1., 1.).asin() / PI,
+            ]
+        });
+        // faces across the u seam take the far side of it
+        let us = [uv0[0], uv1[0], uv2[0]];
+        if us.iter().cloned().fold(0., f32::max) - us.iter().cloned().fold(1., f32::min) > 0.5 {
+            for uv in [&mut uv0, &mut uv1, &mut uv2] {
+                if uv[0] < 0.5 {
+                    uv[0] += 1.;
+                }
+            }
+        }
+        b.triangle([p0, p1, p2], [uv0, uv1, uv2]);
+    }
+    b.build()
+}
+
+/// Closed cylinder of `height` around the y axis.
+pub fn cylinder(radius: f32, height: f32, segments: usize) -> Obj3D {
+    let h = height * 0.5;
+    lathe(&[[0., -h], [radius, -h], [radius, h], [0., h]], segments)
+}
+
+/// Cone of `height` standing on a disc of `radius`.
+pub fn cone(radius: f32, height: f32, segments: usize) -> Obj3D {
+    let h = height * 0.5;
+    lathe(&[[0., -h], [radius, -h], [0., h]], segments)
+}
+
+/// Ring around the y axis, `radius` from the center to the middle of a tube
+/// of radius `tube` cut into `sides`.
+pub fn torus(radius: f32, tube: f32, segments: usize, sides: usize) -> Obj3D {
+    let sides = sides.max(3);
+    let profile: Vec<[f32; 2]> = (0..=sides)
+        .map(|i| {
+            let psi = 2. * PI * i as f32 / sides as f32;
+            [radius + tube * psi.cos(), tube * psi.sin()]
+        })
+        .collect();
+    lathe(&profile, segments)
+}
+
+/// Cylinder ended by half spheres of `rings` each, `height` tall in total.
+pub fn capsule(radius: f32, height: f32, segments: usize, rings: usize) -> Obj3D {
+    let rings = rings.max(1);
+    let h = (height * 0.5 - radius).max(0.);
+    let bottom = (0..=rings).map(|i| {
+        let theta = 0.5 * PI * i as f32 / rings as f32;
+        [radius * theta.sin(), -h - radius * theta.cos()]
+    });
+    let top = (0..=rings).map(|i| {
+        let theta = 0.5 * PI * i as f32 / rings as f32;
+        [radius * theta.cos(), h + radius * theta.sin()]
+    });
+    lathe(&bottom.chain(top).collect::<Vec<_>>(), segments)
+}
+
+/// Arrow from the origin to `length` up the y axis, a shaft of `radius`
+/// with a head twice as wide and a quarter of the length.
+pub fn arrow(length: f32, radius: f32, segments: usize) -> Obj3D {
+    let shaft = length * 0.75;
+    lathe(
+        &[
+            [0., 0.],
+            [radius, 0.],
+            [radius, shaft],
+            [2. * radius, shaft],
+            [0., length],
+        ],
+        segments,
+    )
+}
+
+/// Surface swept by turning the `[radius, y]` points of `profile` around
+/// the y axis. The profile goes upwards along the outside of the shape,
+/// points on the axis close it. v follows the length of the profile.
+fn lathe(profile: &[[f32; 2]], segments: usize) -> Obj3D {
+    let segments = segments.max(3);
+    let mut v = vec![0.; profile.len()];
+    for i in 1..profile.len() {
+        let [r0, y0] = profile[i - 1];
+        let [r1, y1] = profile[i];
+        v[i] = v[i - 1] + (r1 - r0).hypot(y1 - y0);
+    }
+    let length = v.last().copied().unwrap_or(0.).max(f32::EPSILON);
+
+    let mut b = Builder::default();
+    b.grid(segments, profile.len() - 1, |s, t| {
+        let i = (t * (profile.len() - 1) as f32).round() as usize;
+        let [r, y] = profile[i];
+        let phi = 2. * PI * s;
+        ([r * phi.sin(), y, r * phi.cos()], [s, v[i] / length])
+    });
+    b.build()
+}
+
+/// Collects corners and faces before making the `Obj3D`, which appends the
+/// face helper vertexes after the corners.
+#[derive(Default)]
+struct Builder {
+    vertexes: Vec<[f32; 3]>,
+    faces: Vec<([usize; 3], [[f32; 2]; 3])>,
+}
+
+impl Builder {
+    fn vertex(&mut self, p: [f32; 3]) -> usize {
+        self.vertexes.push(p);
+        self.vertexes.len() - 1
+    }
+
+    /// Adds `[a, b, c]` unless it is too thin for a normal, like the ones
+    /// where rows of a grid meet at a pole.
+    fn triangle(&mut self, v: [usize; 3], uvs: [[f32; 2]; 3]) {
+        let [a, b, c] = v.map(|i| self.vertexes[i]);
+        let edges = [diff(b, a), diff(c, b), diff(a, c)];
+        let longest = edges.iter().map(|e| dot(*e, *e)).fold(0., f32::max);
+        let n = cross(edges[0], edges[1]);
+        if dot(n, n).sqrt() > 1e-6 * longest {
+            self.faces.push((v, uvs));
+        }
+    }
+
+    /// Quads between `columns + 1` by `rows + 1` points given by `f` at
+    /// s and t in [0, 1], counter clockwise when turning from s to t.
+    fn grid(&mut self, columns: usize, rows: usize, f: impl Fn(f32, f32) -> ([f32; 3], [f32; 2])) {
+        let start = self.vertexes.len();
+        let mut uvs = Vec::with_capacity((columns + 1) * (rows + 1));
+        for i in 0..=rows {
+            for j in 0..=columns {
+                let (p, uv) = f(j as f32 / columns as f32, i as f32 / rows as f32);
+                self.vertex(p);
+                uvs.push(uv);
+            }
+        }
+
+        let index = |i: usize, j: usize| i * (columns + 1) + j;
+        for i in 0..rows {
+            for j in 0..columns {
+                let [a, b, c, d] = [
+                    index(i, j),
+                    index(i, j + 1),
+                    index(i + 1, j + 1),
+                    index(i + 1, j),
+                ];
+                self.triangle([a, b, c].map(|k| start + k), [uvs[a], uvs[b], uvs[c]]);
+                self.triangle([c, d, a].map(|k| start + k), [uvs[c], uvs[d], uvs[a]]);
+            }
+        }
+    }
+
+    fn build(self) -> Obj3D {
+        let mut obj = Obj3D::new();
+        for p in self.vertexes {
+            obj.push_vertex(Vertex::from_vec(p));
+        }
+        for (v, uvs) in self.faces {
+            obj.push_face(Face::new(v).with_uvs(uvs));
+        }
+        obj
+    }
+}
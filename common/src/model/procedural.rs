@@ -0,0 +1,182 @@
+use noise::{NoiseFn, Perlin};
+use serde::Deserialize;
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pattern {
+    /// Alternating cells of the two colors.
+    Checker,
+    /// Fractal Brownian motion, a sum of Perlin octaves.
+    Fbm { octaves: u32 },
+    /// Sine veins distorted by turbulence.
+    Marble { turbulence: f32 },
+    /// Concentric rings around the y axis, distorted by noise.
+    Wood { rings: f32 },
+    /// Distance to the closest of randomly scattered feature points.
+    Worley,
+}
+
+/// Texture computed from a position instead of read from an image. The
+/// pattern blends between two colors.
+#[derive(Debug, Clone)]
+pub struct Procedural {
+    pub pattern: Pattern,
+    pub colors: [[f32; 3]; 2],
+    /// Pattern repetitions per unit of the input space.
+    pub scale: f32,
+    /// Evaluated at the 3D surface point rather than at the uv.
+    pub solid: bool,
+    seed: u32,
+    perlin: Perlin,
+}
+
+impl Procedural {
+    pub fn new(pattern: Pattern, colors: [[f32; 3]; 2], seed: u32) -> Procedural {
+        Procedural {
+            pattern,
+            colors,
+            scale: 1.,
+            solid: false,
+            seed,
+            perlin: Perlin::new(seed),
+        }
+    }
+
+    pub fn checker(scale: f32, colors: [[f32; 3]; 2]) -> Procedural {
+        Procedural {
+            scale,
+            ..Procedural::new(Pattern::Checker, colors, 0)
+        }
+    }
+
+    pub fn with_scale(self, scale: f32) -> Procedural {
+        Procedural { scale, ..self }
+    }
+
+    pub fn solid(self) -> Procedural {
+        Procedural {
+            solid: true,
+            ..self
+        }
+    }
+
+    /// Color at a surface point. `footprint` is the pixel size in uv units,
+    /// uv patterns fade to their average as features shrink from two pixels
+    /// to one.
+    pub fn sample(&self, point: [f32; 3], uv: [f32; 2], footprint: f32) -> [f32; 3] {
+        let (p, footprint) = if self.solid {
+            (point, 0.)
+        } else {
+            ([uv[0], uv[1], 0.], footprint)
+        };
+        let p = p.map(|c| c * self.scale);
+
+        let fade = smoothstep(0.5, 1., footprint * self.scale);
+        let t = if fade < 1. {
+            let t = self.eval(p);
+            t + (self.average() - t) * fade
+        } else {
+            self.average()
+        };
+
+        let [a, b] = self.colors;
+        [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)
+    }
+
+    /// Blend factor in [0, 1] at `p`, already scaled.
+    pub fn eval(&self, p: [f32; 3]) -> f32 {
+        match self.pattern {
+            // uv patterns have p[2] = 0
+            Pattern::Checker => (p[0].floor() + p[1].floor() + p[2].floor()).rem_euclid(2.),
+            Pattern::Fbm { octaves } => (self.fbm(p, octaves) * 0.5 + 0.5).clamp(0., 1.),
+            Pattern::Marble { turbulence } => {
+                let t = p[0] + turbulence * self.turbulence(p, 6);
+                0.5 + 0.5 * (t * std::f32::consts::PI).sin()
+            }
+            Pattern::Wood { rings } => {
+                let r = (p[0] * p[0] + p[2] * p[2]).sqrt() * rings;
+                let r = r + 0.5 * self.noise(p);
+                r - r.floor()
+            }
+            Pattern::Worley => worley(p, self.seed).min(1.),
+        }
+    }
+
+    fn average(&self) -> f32 {
+        match self.pattern {
+            Pattern::Worley => 0.35,
+            _ => 0.5,
+        }
+    }
+
+    fn noise(&self, p: [f32; 3]) -> f32 {
+        self.perlin.get(p.map(|c| c as f64)) as f32
+    }
+
+    /// Sum of octaves of halving amplitude and doubling frequency, about [-1, 1].
+    pub fn fbm(&self, p: [f32; 3], octaves: u32) -> f32 {
+        let mut sum = 0.;
+        let mut amplitude = 0.5;
+        let mut frequency = 1.;
+        for _ in 0..octaves {
+            sum += amplitude * self.noise(p.map(|c| c * frequency));
+            amplitude *= 0.5;
+            frequency *= 2.;
+        }
+        sum
+    }
+
+    /// fBm of the absolute noise value, in [0, 1).
+    pub fn turbulence(&self, p: [f32; 3], octaves: u32) -> f32 {
+        let mut sum = 0.;
+        let mut amplitude = 0.5;
+        let mut frequency = 1.;
+        for _ in 0..octaves {
+            sum += amplitude * self.noise(p.map(|c| c * frequency)).abs();
+            amplitude *= 0.5;
+            frequency *= 2.;
+        }
+        sum
+    }
+}
+
+/// Distance from `p` to the nearest feature point, one point per unit cell.
+pub fn worley(p: [f32; 3], seed: u32) -> f32 {
+    let cell = p.map(|c| c.floor() as i32);
+    let mut nearest = f32::INFINITY;
+
+    for dz in -1..=1 {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let c = [cell[0] + dx, cell[1] + dy, cell[2] + dz];
+                let h = hash(c, seed);
+                let feature = [
+                    c[0] as f32 + (h & 0x3ff) as f32 / 1024.,
+                    c[1] as f32 + ((h >> 10) & 0x3ff) as f32 / 1024.,
+                    c[2] as f32 + ((h >> 20) & 0x3ff) as f32 / 1024.,
+                ];
+                let d = [0, 1, 2].map(|i| feature[i] - p[i]);
+                nearest = nearest.min(d[0] * d[0] + d[1] * d[1] + d[2] * d[2]);
+            }
+        }
+    }
+
+    nearest.sqrt()
+}
+
+fn hash(c: [i32; 3], seed: u32) -> u32 {
+    let mut h = seed
+        ^ (c[0] as u32).wrapping_mul(0x8da6b343)
+        ^ (c[1] as u32).wrapping_mul(0xd8163841)
+        ^ (c[2] as u32).wrapping_mul(0xcb1ab31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846ca68b);
+    h ^ (h >> 16)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
@@ -0,0 +1,328 @@
+use nannou::image::{Rgba, RgbaImage};
+
+use super::aov::{Aov, AovBuffers};
+use super::environment::Environment;
+use super::figure::{Face, Instance, Mesh, Obj3D, Vertex};
+use super::mat::{add, comp_dot, cross, diff, dot, mul, neg, unit, Mat4x4};
+use super::material::Material;
+use super::texture::srgb_encode;
+
+/// Software rasterizer filling projected faces with a depth test.
+#[derive(Debug, Clone)]
+pub struct Raster {
+    pub width: usize,
+    pub height: usize,
+    /// Linear colors, row major from the top left corner. Alpha is 0 where
+    /// nothing was drawn.
+    pub color: Vec<[f32; 4]>,
+    /// 1/w of the closest fragment, 0 where nothing was drawn.
+    pub depth: Vec<f32>,
+    /// Extra outputs of the closest fragments, none unless asked for.
+    pub aovs: AovBuffers,
+}
+
+/// Triangle corner in pixel coordinates.
+#[derive(Debug, Copy, Clone)]
+struct Corner {
+    x: f32,
+    y: f32,
+    inv_w: f32,
+    uv: [f32; 2],
+    color: [f32; 3],
+    /// World position.
+    p: [f32; 3],
+    /// Clip x, y and w in the previous frame.
+    previous: [f32; 3],
+}
+
+/// Where a projected face comes from.
+struct Source<'a> {
+    /// World space vertexes, indexed like the projected ones.
+    world: &'a [Vertex],
+    /// The vertexes projected with the previous frame's matrices.
+    previous: &'a [Vertex],
+    normal: [f32; 3],
+    /// Multiplies the albedo, as do the face's corner colors.
+    tint: [f32; 3],
+    id: usize,
+}
+
+impl Raster {
+    pub fn new(width: usize, height: usize) -> Raster {
+        Raster {
+            width,
+            height,
+            color: vec![[0., 0., 0., 0.]; width * height],
+            depth: vec![0.; width * height],
+            aovs: AovBuffers::new(width, height, &[]),
+        }
+    }
+
+    pub fn with_aovs(self, aovs: &[Aov]) -> Raster {
+        Raster {
+            aovs: AovBuffers::new(self.width, self.height, aovs),
+            ..self
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.color.fill([0., 0., 0., 0.]);
+        self.depth.fill(0.);
+        self.aovs.clear();
+    }
+
+    /// Fills the pixels not covered by faces with the environment seen by a
+    /// camera at `eye` looking at `at`, with a vertical field of view of `fov`
+    /// degrees.
+    pub fn draw_environment(
+        &mut self,
+        env: &Environment,
+        eye: [f32; 3],
+        at: [f32; 3],
+        up: [f32; 3],
+        fov: f32,
+    ) {
+        let n = unit(diff(eye, at));
+        let u = unit(cross(up, n));
+        let v = cross(n, u);
+        let scale = (fov * 0.5 * std::f32::consts::PI / 180.).tan();
+        let ratio = self.width as f32 / self.height as f32;
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let idx = y * self.width + x;
+                if self.depth[idx] > 0. {
+                    continue;
+                }
+
+                let sx = (2. * (x as f32 + 0.5) / self.width as f32 - 1.) * ratio * scale;
+                let sy = (1. - 2. * (y as f32 + 0.5) / self.height as f32) * scale;
+                let dir = unit(add(add(mul(u, sx), mul(v, sy)), neg(n)));
+                let c = env.radiance(dir);
+                self.color[idx] = [c[0], c[1], c[2], 1.];
+            }
+        }
+    }
+
+    /// Fills the faces of `clip`, the mesh after `projection * camera`, whose
+    /// vertexes keep their clip w. `world` is the same mesh before the camera,
+    /// its face normals shade the faces against a directional `light`.
+    pub fn draw_mesh(&mut self, world: &Mesh, clip: &Mesh, light: [f32; 3]) {
+        self.draw_moving_mesh(world, clip, clip, light);
+    }
+
+    /// `draw_mesh` also given the mesh projected for the previous frame,
+    /// which the motion AOV is measured against.
+    pub fn draw_moving_mesh(
+        &mut self,
+        world: &Mesh,
+        clip: &Mesh,
+        previous: &Mesh,
+        light: [f32; 3],
+    ) {
+        let to_light = unit(neg(light));
+        for (id, (name, obj)) in clip.objects.iter().enumerate() {
+            let (world_obj, previous) = match (world.objects.get(name), previous.objects.get(name))
+            {
+                (Some(world_obj), Some(previous)) => (world_obj, previous),
+                _ => continue,
+            };
+
+            for face in obj.faces.iter() {
+                let source = Source {
+                    world: &world_obj.vertexes,
+                    previous: &previous.vertexes,
+                    normal: face_normal(&world_obj.vertexes, face),
+                    tint: [1., 1., 1.],
+                    id,
+                };
+                let shade = 0.2 + 0.8 * dot(source.normal, to_light).max(0.);
+                self.draw_face(&obj.vertexes, face, &obj.material, &source, shade);
+            }
+        }
+    }
+
+    /// Fills the faces of `obj` once per instance, moving its model space
+    /// vertexes to world space and projecting them with `view_proj` on the
+    /// fly instead of copying the object. `previous_view_proj` is the
+    /// projection of the previous frame, `id` is written to the id AOV.
+    pub fn draw_instances(
+        &mut self,
+        obj: &Obj3D,
+        instances: &[Instance],
+        view_proj: Mat4x4,
+        previous_view_proj: Mat4x4,
+        light: [f32; 3],
+        id: usize,
+    ) {
+        let to_light = unit(neg(light));
+        // reused by every instance
+        let mut world = Vec::with_capacity(obj.vertexes.len());
+        let mut clip = Vec::with_capacity(obj.vertexes.len());
+        let mut previous = Vec::with_capacity(obj.vertexes.len());
+
+        for instance in instances {
+            world.clear();
+            clip.clear();
+            previous.clear();
+            for v in obj.vertexes.iter() {
+                let p = instance.transform * v.to_vec();
+                world.push(Vertex::from(p));
+                clip.push(Vertex::from(view_proj * p));
+                previous.push(Vertex::from(previous_view_proj * p));
+            }
+
+            for face in obj.faces.iter() {
+                let source = Source {
+                    world: &world,
+                    previous: &previous,
+                    normal: triangle_normal(&world, face),
+                    tint: instance.color,
+                    id,
+                };
+                let shade = 0.2 + 0.8 * dot(source.normal, to_light).max(0.);
+                self.draw_face(&clip, face, &obj.material, &source, shade);
+            }
+        }
+    }
+
+    fn draw_face(
+        &mut self,
+        clip: &[Vertex],
+        face: &Face,
+        material: &Material,
+        source: &Source,
+        shade: f32,
+    ) {
+        let uvs = face.uvs.unwrap_or([[0., 0.], [1., 0.], [0., 1.]]);
+        let colors = face.colors.unwrap_or([[1., 1., 1.]; 3]);
+        let mut corners = [Corner {
+            x: 0.,
+            y: 0.,
+            inv_w: 0.,
+            uv: [0., 0.],
+            color: [1., 1., 1.],
+            p: [0., 0., 0.],
+            previous: [0., 0., 0.],
+        }; 3];
+
+        for i in 0..3 {
+            let v = clip[face.vertexes[i]];
+            // no near plane clipping, faces reaching behind the eye are dropped
+            if v.w <= 1e-4 {
+                return;
+            }
+            let prev = source.previous[face.vertexes[i]];
+            corners[i] = Corner {
+                x: (v.x + 1.) * 0.5 * self.width as f32,
+                y: (1. - v.y) * 0.5 * self.height as f32,
+                inv_w: 1. / v.w,
+                uv: uvs[i],
+                color: colors[i],
+                p: source.world[face.vertexes[i]].to_vec_3(),
+                previous: [prev.x * prev.w, prev.y * prev.w, prev.w],
+            };
+        }
+
+        let [a, b, c] = corners;
+        let (a_inv, b_inv, c_inv) = (a.inv_w, b.inv_w, c.inv_w);
+        let area = edge(a, b, c.x, c.y);
+        if area.abs() < 1e-9 {
+            return;
+        }
+
+        let min_x = a.x.min(b.x).min(c.x).floor().max(0.) as usize;
+        let min_y = a.y.min(b.y).min(c.y).floor().max(0.) as usize;
+        let max_x = (a.x.max(b.x).max(c.x).ceil() as usize).min(self.width);
+        let max_y = (a.y.max(b.y).max(c.y).ceil() as usize).min(self.height);
+
+        // perspective correct interpolation: attribute/w and 1/w are linear on screen
+        let interpolate = |x: f32, y: f32| {
+            let w = [
+                edge(b, c, x, y) / area,
+                edge(c, a, x, y) / area,
+                edge(a, b, x, y) / area,
+            ];
+            let inv_w = w[0] * a_inv + w[1] * b_inv + w[2] * c_inv;
+            let lerp = |a: f32, b: f32, c: f32| {
+                (w[0] * a * a_inv + w[1] * b * b_inv + w[2] * c * c_inv) / inv_w
+            };
+            let uv = [0, 1].map(|k| lerp(a.uv[k], b.uv[k], c.uv[k]));
+            let color = [0, 1, 2].map(|k| lerp(a.color[k], b.color[k], c.color[k]));
+            let p = [0, 1, 2].map(|k| lerp(a.p[k], b.p[k], c.p[k]));
+            let previous = [0, 1, 2].map(|k| lerp(a.previous[k], b.previous[k], c.previous[k]));
+            (w, inv_w, uv, color, p, previous)
+        };
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let (w, inv_w, uv, color, p, previous) = interpolate(px, py);
+                if w.iter().any(|w| *w < 0.) {
+                    continue;
+                }
+
+                let idx = y * self.width + x;
+                if inv_w <= self.depth[idx] {
+                    continue;
+                }
+                self.depth[idx] = inv_w;
+
+                // screen space derivatives pick the mip level
+                let footprint = if material.albedo_map.is_some() {
+                    let (_, _, du, ..) = interpolate(px + 1., py);
+                    let (_, _, dv, ..) = interpolate(px, py + 1.);
+                    let dx = (du[0] - uv[0]).hypot(du[1] - uv[1]);
+                    let dy = (dv[0] - uv[0]).hypot(dv[1] - uv[1]);
+                    dx.max(dy)
+                } else {
+                    0.
+                };
+
+                let albedo = comp_dot(material.albedo_at(p, uv, footprint), source.tint);
+                let albedo = comp_dot(albedo, color);
+                let c = [0, 1, 2].map(|k| albedo[k] * shade + material.emission[k]);
+                self.color[idx] = [c[0], c[1], c[2], 1.];
+
+                if !self.aovs.is_empty() {
+                    let prev_x = (previous[0] / previous[2] + 1.) * 0.5 * self.width as f32;
+                    let prev_y = (1. - previous[1] / previous[2]) * 0.5 * self.height as f32;
+                    let aovs = &mut self.aovs;
+                    aovs.set(Aov::Depth, x, y, &[1. / inv_w]);
+                    aovs.set(Aov::Normal, x, y, &source.normal);
+                    aovs.set(Aov::Albedo, x, y, &albedo);
+                    aovs.set(Aov::ObjectId, x, y, &[source.id as f32 + 1.]);
+                    aovs.set(Aov::Uv, x, y, &uv);
+                    aovs.set(Aov::Motion, x, y, &[px - prev_x, py - prev_y]);
+                }
+            }
+        }
+    }
+
+    /// sRGB image of the frame, pixels nothing was drawn on are transparent.
+    pub fn to_image(&self) -> RgbaImage {
+        RgbaImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let c = self.color[y as usize * self.width + x as usize];
+            let [r, g, b] = [c[0], c[1], c[2]].map(|v| (srgb_encode(v) * 255. + 0.5) as u8);
+            Rgba([r, g, b, (c[3].clamp(0., 1.) * 255. + 0.5) as u8])
+        })
+    }
+}
+
+fn face_normal(vertexes: &[Vertex], face: &Face) -> [f32; 3] {
+    let c = vertexes[face.center_vertex as usize].to_vec_3();
+    let n = vertexes[face.normal_vertex as usize].to_vec_3();
+    unit(diff(n, c))
+}
+
+/// Normal of the transformed triangle itself, the helper vertexes of the
+/// face are skewed by non uniform scales.
+fn triangle_normal(vertexes: &[Vertex], face: &Face) -> [f32; 3] {
+    let [a, b, c] = face.vertexes.map(|i| vertexes[i].to_vec_3());
+    unit(cross(diff(b, a), diff(c, b)))
+}
+
+/// Twice the signed area of the triangle (a, b, p).
+fn edge(a: Corner, b: Corner, x: f32, y: f32) -> f32 {
+    (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+}
@@ -0,0 +1,565 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use super::environment::{EnvMap, Environment};
+use super::figure::Obj3D;
+use super::graph::SceneGraph;
+use super::light::Light;
+use super::mat::Mat4x4;
+use super::material::Material;
+use super::primitives::{
+    arrow, capsule, cone, cuboid, cylinder, icosphere, plane, torus, uv_sphere,
+};
+use super::procedural::{Pattern, Procedural};
+use super::texture::{Texture, TextureMap};
+use super::transform::Transform;
+use super::wavefront::load_obj;
+
+/// Camera of a scene file, `fov` is the vertical field of view in degrees.
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub struct Camera {
+    pub eye: [f32; 3],
+    pub at: [f32; 3],
+    #[serde(default = "up")]
+    pub up: [f32; 3],
+    #[serde(default = "fov")]
+    pub fov: f32,
+    /// Lens radius, 0 for a pinhole.
+    #[serde(default)]
+    pub aperture: f32,
+    /// Distance in focus, the distance to `at` when missing.
+    #[serde(default)]
+    pub focus_distance: Option<f32>,
+}
+
+/// Contents of a scene file, loaded into a `SceneGraph` with `load_scene`.
+///
+/// ```json
+/// {
+///   "camera": {"eye": [0, 1, 3], "at": [0, 0, 0], "fov": 45},
+///   "environment": "sky",
+///   "lights": [{"type": "point", "position": [1, 2, 1], "intensity": [5, 5, 5]}],
+///   "materials": {"red": {"type": "lambertian", "albedo": [0.8, 0.1, 0.1]}},
+///   "objects": [
+///     {"name": "box", "primitive": {"type": "cube"}, "material": "red",
+///      "transform": {"translate": [0, 0.5, 0], "rotate": {"axis": [0, 1, 0], "angle": 30}}},
+///     {"name": "teapot", "mesh": "teapot.obj", "parent": "box",
+///      "transform": {"translate": [0, 0.5, 0], "scale": 0.1}}
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct SceneDesc {
+    pub camera: Camera,
+    #[serde(default)]
+    pub environment: EnvironmentDesc,
+    #[serde(default)]
+    pub lights: Vec<Light>,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default)]
+    pub objects: Vec<ObjectDesc>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvironmentDesc {
+    #[default]
+    Sky,
+    Black,
+    Constant([f32; 3]),
+    Gradient {
+        zenith: [f32; 3],
+        horizon: [f32; 3],
+        ground: [f32; 3],
+    },
+    /// Path of an equirectangular HDR image.
+    Map(String),
+}
+
+/// Named material, `{"type": "metal", "albedo": [...], "roughness": 0.2}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MaterialDesc {
+    #[serde(flatten)]
+    pub base: BaseMaterial,
+    #[serde(default)]
+    pub emission: Option<[f32; 3]>,
+    /// Multiplies the albedo.
+    #[serde(default)]
+    pub texture: Option<TextureDesc>,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BaseMaterial {
+    Lambertian {
+        albedo: [f32; 3],
+    },
+    Metal {
+        albedo: [f32; 3],
+        #[serde(default)]
+        roughness: f32,
+    },
+    Dielectric {
+        ior: f32,
+    },
+    Emissive {
+        emission: [f32; 3],
+    },
+    Pbr {
+        albedo: [f32; 3],
+        #[serde(default)]
+        metallic: f32,
+        #[serde(default = "roughness")]
+        roughness: f32,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TextureDesc {
+    Image {
+        path: String,
+    },
+    /// `pattern` is `"checker"`, `"worley"` or e.g. `{"marble": {"turbulence": 1.5}}`.
+    Procedural {
+        pattern: Pattern,
+        colors: [[f32; 3]; 2],
+        #[serde(default = "one")]
+        scale: f32,
+        #[serde(default)]
+        solid: bool,
+        #[serde(default)]
+        seed: u32,
+    },
+}
+
+/// Object read from an OBJ `mesh` file or built from a `primitive`, or
+/// with neither a group moving its children.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ObjectDesc {
+    pub name: String,
+    /// Object listed earlier that the transform is relative to.
+    #[serde(default)]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub mesh: Option<String>,
+    #[serde(default)]
+    pub primitive: Option<PrimitiveDesc>,
+    /// Name of a material of the file, replacing the ones of the mesh.
+    #[serde(default)]
+    pub material: Option<String>,
+    /// Multiplies the albedo of this placement, other placements of the same
+    /// mesh keep sharing its geometry.
+    #[serde(default)]
+    pub color: Option<[f32; 3]>,
+    #[serde(default)]
+    pub transform: TransformDesc,
+}
+
+/// Shapes of `primitives`, centered on the origin except the arrow which
+/// starts there. Curved ones are cut into `segments` around the y axis.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PrimitiveDesc {
+    Cube {
+        #[serde(default = "one")]
+        size: f32,
+        #[serde(default = "single")]
+        segments: usize,
+    },
+    Box {
+        #[serde(default = "unit_size")]
+        size: [f32; 3],
+        #[serde(default = "single")]
+        segments: usize,
+    },
+    /// In y = 0 facing up.
+    Plane {
+        #[serde(default = "one")]
+        size: f32,
+        #[serde(default = "single")]
+        segments: usize,
+    },
+    Sphere {
+        #[serde(default = "half")]
+        radius: f32,
+        #[serde(default = "segments")]
+        segments: usize,
+        #[serde(default = "rings")]
+        rings: usize,
+    },
+    Icosphere {
+        #[serde(default = "half")]
+        radius: f32,
+        #[serde(default = "subdivisions")]
+        subdivisions: usize,
+    },
+    Cylinder {
+        #[serde(default = "half")]
+        radius: f32,
+        #[serde(default = "one")]
+        height: f32,
+        #[serde(default = "segments")]
+        segments: usize,
+    },
+    Cone {
+        #[serde(default = "half")]
+        radius: f32,
+        #[serde(default = "one")]
+        height: f32,
+        #[serde(default = "segments")]
+        segments: usize,
+    },
+    Torus {
+        #[serde(default = "half")]
+        radius: f32,
+        #[serde(default = "tube")]
+        tube: f32,
+        #[serde(default = "segments")]
+        segments: usize,
+        #[serde(default = "rings")]
+        sides: usize,
+    },
+    /// `rings` for each half sphere.
+    Capsule {
+        #[serde(default = "tube")]
+        radius: f32,
+        #[serde(default = "one")]
+        height: f32,
+        #[serde(default = "segments")]
+        segments: usize,
+        #[serde(default = "rings")]
+        rings: usize,
+    },
+    /// Up the y axis.
+    Arrow {
+        #[serde(default = "one")]
+        length: f32,
+        #[serde(default = "shaft")]
+        radius: f32,
+        #[serde(default = "segments")]
+        segments: usize,
+    },
+}
+
+#[derive(Debug, Copy, Clone, Default, Deserialize)]
+pub struct TransformDesc {
+    #[serde(default)]
+    pub translate: [f32; 3],
+    #[serde(default)]
+    pub rotate: Option<Rotation>,
+    #[serde(default)]
+    pub scale: Scale,
+}
+
+/// Rotation by `angle` degrees around `axis`.
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub struct Rotation {
+    pub axis: [f32; 3],
+    pub angle: f32,
+}
+
+/// A single factor or one per axis.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Scale {
+    Uniform(f32),
+    Axes([f32; 3]),
+}
+
+impl Default for Scale {
+    fn default() -> Scale {
+        Scale::Uniform(1.)
+    }
+}
+
+impl TransformDesc {
+    pub fn to_transform(&self) -> Transform {
+        let mut t = Transform::new().translate(self.translate);
+        if let Some(r) = self.rotate {
+            t = t.rotate(r.axis, r.angle.to_radians());
+        }
+        match self.scale {
+            Scale::Uniform(s) => t.scale([s, s, s]),
+            Scale::Axes(s) => t.scale(s),
+        }
+    }
+}
+
+/// Scene file with its meshes loaded.
+#[derive(Debug, Clone)]
+pub struct Scene {
+    pub camera: Camera,
+    pub environment: Environment,
+    pub lights: Vec<Light>,
+    /// One node per scene object, with a child per part of multi object
+    /// mesh files.
+    pub graph: SceneGraph,
+}
+
+/// Part names of a mesh file with the graph objects holding them.
+type Parts = Vec<(String, usize)>;
+
+/// Parses a JSON scene, files are looked up relative to `dir`.
+pub fn parse_scene(src: &str, dir: &Path) -> Result<Scene, String> {
+    let file: SceneDesc = serde_json::from_str(src).map_err(|e| e.to_string())?;
+
+    let environment = match &file.environment {
+        EnvironmentDesc::Sky => Environment::sky(),
+        EnvironmentDesc::Black => Environment::default(),
+        EnvironmentDesc::Constant(c) => Environment::Constant(*c),
+        EnvironmentDesc::Gradient {
+            zenith,
+            horizon,
+            ground,
+        } => Environment::Gradient {
+            zenith: *zenith,
+            horizon: *horizon,
+            ground: *ground,
+        },
+        EnvironmentDesc::Map(path) => {
+            let map = EnvMap::load(dir.join(path)).map_err(|e| format!("{}: {}", path, e))?;
+            Environment::Map(Arc::new(map))
+        }
+    };
+
+    let mut materials = BTreeMap::new();
+    for (name, desc) in file.materials.iter() {
+        materials.insert(name.as_str(), material(desc, dir)?);
+    }
+
+    let mut graph = SceneGraph::new();
+    let mut nodes: HashMap<&str, usize> = HashMap::new();
+    // files placed several times are loaded once per material override
+    let mut loaded: HashMap<(&str, Option<&str>), Parts> = HashMap::new();
+
+    for desc in file.objects.iter() {
+        if nodes.contains_key(desc.name.as_str()) {
+            return Err(format!("duplicate object {}", desc.name));
+        }
+        let material = match desc.material.as_ref() {
+            Some(m) => Some(
+                materials
+                    .get(m.as_str())
+                    .ok_or(format!("object {}: unknown material {}", desc.name, m))?,
+            ),
+            None => None,
+        };
+        let parent = match desc.parent.as_ref() {
+            Some(p) => Some(*nodes.get(p.as_str()).ok_or(format!(
+                "object {}: parent {} must be listed before it",
+                desc.name, p
+            ))?),
+            None => None,
+        };
+
+        let parts = match (&desc.mesh, &desc.primitive) {
+            (Some(path), None) => {
+                let key = (path.as_str(), desc.material.as_deref());
+                match loaded.entry(key) {
+                    Entry::Occupied(e) => e.get().clone(),
+                    Entry::Vacant(e) => {
+                        let mesh =
+                            load_obj(dir.join(path)).map_err(|e| format!("{}: {}", path, e))?;
+                        let mut parts = vec![];
+                        for (part, mut obj) in mesh.objects {
+                            if let Some(material) = material {
+                                obj.set_material(material.clone());
+                            }
+                            parts.push((part, graph.push_object(obj)));
+                        }
+                        e.insert(parts).clone()
+                    }
+                }
+            }
+            (None, Some(primitive)) => {
+                let mut obj = primitive.build();
+                if let Some(material) = material {
+                    obj.set_material(material.clone());
+                }
+                vec![(desc.name.clone(), graph.push_object(obj))]
+            }
+            // a group only placing its children
+            (None, None) => vec![],
+            _ => {
+                return Err(format!(
+                    "object {}: has both a mesh and a primitive",
+                    desc.name
+                ))
+            }
+        };
+
+        let local = desc.transform.to_transform().to_mat();
+        let node = match parts.as_slice() {
+            [(_, object)] => graph.push_node(&desc.name, parent, local, Some(*object)),
+            _ => {
+                let node = graph.push_node(&desc.name, parent, local, None);
+                for (part, object) in parts.iter() {
+                    graph.push_node(part, Some(node), Mat4x4::unit(), Some(*object));
+                }
+                node
+            }
+        };
+        if let Some(color) = desc.color {
+            for id in std::iter::once(node).chain(graph.node(node).children.clone()) {
+                graph.set_color(id, color);
+            }
+        }
+        nodes.insert(&desc.name, node);
+    }
+
+    Ok(Scene {
+        camera: file.camera,
+        environment,
+        lights: file.lights,
+        graph,
+    })
+}
+
+/// Loads a JSON scene file and the meshes and images it references.
+pub fn load_scene<P: AsRef<Path>>(path: P) -> io::Result<Scene> {
+    let path = path.as_ref();
+    let src = fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    parse_scene(&src, dir).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn material(desc: &MaterialDesc, dir: &Path) -> Result<Material, String> {
+    let mut material = match desc.base {
+        BaseMaterial::Lambertian { albedo } => Material::lambertian(albedo),
+        BaseMaterial::Metal { albedo, roughness } => Material::metal(albedo, roughness),
+        BaseMaterial::Dielectric { ior } => Material::dielectric(ior),
+        BaseMaterial::Emissive { emission } => Material::emissive(emission),
+        BaseMaterial::Pbr {
+            albedo,
+            metallic,
+            roughness,
+        } => Material::pbr(albedo, metallic, roughness),
+    };
+    if let Some(emission) = desc.emission {
+        material = material.with_emission(emission);
+    }
+
+    match &desc.texture {
+        None => Ok(material),
+        Some(TextureDesc::Image { path }) => {
+            let texture = Texture::load(dir.join(path)).map_err(|e| format!("{}: {}", path, e))?;
+            Ok(material.with_albedo_map(TextureMap::Image(Arc::new(texture))))
+        }
+        Some(TextureDesc::Procedural {
+            pattern,
+            colors,
+            scale,
+            solid,
+            seed,
+        }) => {
+            let mut procedural = Procedural::new(*pattern, *colors, *seed).with_scale(*scale);
+            if *solid {
+                procedural = procedural.solid();
+            }
+            Ok(material.with_albedo_map(TextureMap::Procedural(Arc::new(procedural))))
+        }
+    }
+}
+
+impl PrimitiveDesc {
+    pub fn build(&self) -> Obj3D {
+        match *self {
+            PrimitiveDesc::Cube { size, segments } => cuboid([size; 3], segments),
+            PrimitiveDesc::Box { size, segments } => cuboid(size, segments),
+            PrimitiveDesc::Plane { size, segments } => plane(size, segments),
+            PrimitiveDesc::Sphere {
+                radius,
+                segments,
+                rings,
+            } => uv_sphere(radius, segments, rings),
+            PrimitiveDesc::Icosphere {
+                radius,
+                subdivisions,
+            } => icosphere(radius, subdivisions),
+            PrimitiveDesc::Cylinder {
+                radius,
+                height,
+                segments,
+            } => cylinder(radius, height, segments),
+            PrimitiveDesc::Cone {
+                radius,
+                height,
+                segments,
+            } => cone(radius, height, segments),
+            PrimitiveDesc::Torus {
+                radius,
+                tube,
+                segments,
+                sides,
+            } => torus(radius, tube, segments, sides),
+            PrimitiveDesc::Capsule {
+                radius,
+                height,
+                segments,
+                rings,
+            } => capsule(radius, height, segments, rings),
+            PrimitiveDesc::Arrow {
+                length,
+                radius,
+                segments,
+            } => arrow(length, radius, segments),
+        }
+    }
+}
+
+fn up() -> [f32; 3] {
+    [0., 1., 0.]
+}
+
+fn fov() -> f32 {
+    60.
+}
+
+fn roughness() -> f32 {
+    0.5
+}
+
+fn one() -> f32 {
+    1.
+}
+
+fn half() -> f32 {
+    0.5
+}
+
+fn unit_size() -> [f32; 3] {
+    [1., 1., 1.]
+}
+
+fn tube() -> f32 {
+    0.15
+}
+
+fn shaft() -> f32 {
+    0.05
+}
+
+fn single() -> usize {
+    1
+}
+
+fn segments() -> usize {
+    32
+}
+
+fn rings() -> usize {
+    16
+}
+
+fn subdivisions() -> usize {
+    2
+}
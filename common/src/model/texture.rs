@@ -0,0 +1,250 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use nannou::image::{self, ImageResult};
+
+use super::procedural::Procedural;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Filtering {
+    Nearest,
+    Bilinear,
+    /// Bilinear lookups in the two closest mip levels, blended by LOD.
+    Trilinear,
+}
+
+/// One level of the mip chain, linear RGBA.
+#[derive(Clone)]
+struct Level {
+    width: usize,
+    height: usize,
+    data: Vec<[f32; 4]>,
+}
+
+/// Image texture with a full mip chain. Texels are stored in linear space.
+#[derive(Clone)]
+pub struct Texture {
+    levels: Vec<Level>,
+    pub wrap: Wrap,
+    pub filtering: Filtering,
+}
+
+impl fmt::Debug for Texture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Texture")
+            .field("width", &self.width())
+            .field("height", &self.height())
+            .field("levels", &self.levels.len())
+            .field("wrap", &self.wrap)
+            .field("filtering", &self.filtering)
+            .finish()
+    }
+}
+
+impl Texture {
+    /// Texture from row major linear RGBA texels.
+    pub fn new(width: usize, height: usize, data: Vec<[f32; 4]>) -> Texture {
+        assert_eq!(data.len(), width * height);
+
+        let mut levels = vec![Level {
+            width,
+            height,
+            data,
+        }];
+        while let Some(next) = levels.last().and_then(|l| l.downsample()) {
+            levels.push(next);
+        }
+
+        Texture {
+            levels,
+            wrap: Wrap::Repeat,
+            filtering: Filtering::Trilinear,
+        }
+    }
+
+    /// Loads a PNG or JPEG file, decoding sRGB colors to linear.
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<Texture> {
+        let img = image::open(path)?.to_rgba8();
+        let (width, height) = img.dimensions();
+
+        let data = img
+            .pixels()
+            .map(|p| {
+                [
+                    srgb_decode(p[0]),
+                    srgb_decode(p[1]),
+                    srgb_decode(p[2]),
+                    p[3] as f32 / 255.,
+                ]
+            })
+            .collect();
+
+        Ok(Texture::new(width as usize, height as usize, data))
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    /// Samples at `uv`, where `footprint` is the size of the area covered
+    /// by the pixel in uv units. It selects the mip level.
+    pub fn sample(&self, uv: [f32; 2], footprint: f32) -> [f32; 4] {
+        match self.filtering {
+            Filtering::Nearest => self.levels[0].nearest(uv, self.wrap),
+            Filtering::Bilinear => self.levels[0].bilinear(uv, self.wrap),
+            Filtering::Trilinear => {
+                let texels = footprint * self.width().max(self.height()) as f32;
+                let lod = texels.max(1.).log2().min((self.levels.len() - 1) as f32);
+                let base = lod.floor() as usize;
+                let t = lod - base as f32;
+
+                let a = self.levels[base].bilinear(uv, self.wrap);
+                if t == 0.0 || base + 1 >= self.levels.len() {
+                    return a;
+                }
+                let b = self.levels[base + 1].bilinear(uv, self.wrap);
+                [0, 1, 2, 3].map(|i| a[i] + (b[i] - a[i]) * t)
+            }
+        }
+    }
+}
+
+impl Level {
+    fn downsample(&self) -> Option<Level> {
+        if self.width == 1 && self.height == 1 {
+            return None;
+        }
+
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut data = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut c = [0.; 4];
+                let xs = [(2 * x).min(self.width - 1), (2 * x + 1).min(self.width - 1)];
+                let ys = [
+                    (2 * y).min(self.height - 1),
+                    (2 * y + 1).min(self.height - 1),
+                ];
+                for sy in ys {
+                    for sx in xs {
+                        let t = self.data[sy * self.width + sx];
+                        for i in 0..4 {
+                            c[i] += t[i] * 0.25;
+                        }
+                    }
+                }
+                data.push(c);
+            }
+        }
+
+        Some(Level {
+            width,
+            height,
+            data,
+        })
+    }
+
+    fn texel(&self, x: i64, y: i64, wrap: Wrap) -> [f32; 4] {
+        let x = wrap_index(x, self.width, wrap);
+        let y = wrap_index(y, self.height, wrap);
+        self.data[y * self.width + x]
+    }
+
+    fn nearest(&self, uv: [f32; 2], wrap: Wrap) -> [f32; 4] {
+        let x = (uv[0] * self.width as f32).floor() as i64;
+        let y = ((1. - uv[1]) * self.height as f32).floor() as i64;
+        self.texel(x, y, wrap)
+    }
+
+    fn bilinear(&self, uv: [f32; 2], wrap: Wrap) -> [f32; 4] {
+        // texel centers sit at half integer coordinates, v = 0 is the bottom row
+        let x = uv[0] * self.width as f32 - 0.5;
+        let y = (1. - uv[1]) * self.height as f32 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let a = self.texel(x0, y0, wrap);
+        let b = self.texel(x0 + 1, y0, wrap);
+        let c = self.texel(x0, y0 + 1, wrap);
+        let d = self.texel(x0 + 1, y0 + 1, wrap);
+
+        [0, 1, 2, 3].map(|i| {
+            let top = a[i] + (b[i] - a[i]) * tx;
+            let bottom = c[i] + (d[i] - c[i]) * tx;
+            top + (bottom - top) * ty
+        })
+    }
+}
+
+fn wrap_index(i: i64, size: usize, wrap: Wrap) -> usize {
+    let n = size as i64;
+    match wrap {
+        Wrap::Repeat => i.rem_euclid(n) as usize,
+        Wrap::Clamp => i.clamp(0, n - 1) as usize,
+        Wrap::Mirror => {
+            let period = i.rem_euclid(2 * n);
+            if period < n {
+                period as usize
+            } else {
+                (2 * n - 1 - period) as usize
+            }
+        }
+    }
+}
+
+/// Spatially varying color input of a material.
+#[derive(Debug, Clone)]
+pub enum TextureMap {
+    Image(Arc<Texture>),
+    Procedural(Arc<Procedural>),
+}
+
+impl TextureMap {
+    /// Color at a surface point, `footprint` is the pixel size in uv units.
+    pub fn sample(&self, point: [f32; 3], uv: [f32; 2], footprint: f32) -> [f32; 3] {
+        match self {
+            TextureMap::Image(t) => {
+                let c = t.sample(uv, footprint);
+                [c[0], c[1], c[2]]
+            }
+            TextureMap::Procedural(p) => p.sample(point, uv, footprint),
+        }
+    }
+}
+
+/// sRGB 8 bit value to linear [0, 1].
+pub fn srgb_decode(v: u8) -> f32 {
+    let v = v as f32 / 255.;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Linear to sRGB transfer function, clamping to [0, 1].
+pub fn srgb_encode(v: f32) -> f32 {
+    let v = v.clamp(0., 1.);
+    if v <= 0.0031308 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1. / 2.4) - 0.055
+    }
+}
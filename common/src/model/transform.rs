@@ -0,0 +1,112 @@
+use super::mat::{scale_mat, translation_mat, unit, Mat4x4};
+
+/// Scale, then rotation, then translation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub translation: [f32; 3],
+    /// Unit quaternion `[x, y, z, w]`.
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl Transform {
+    pub fn new() -> Transform {
+        Transform {
+            translation: [0., 0., 0.],
+            rotation: [0., 0., 0., 1.],
+            scale: [1., 1., 1.],
+        }
+    }
+
+    pub fn translate(self, t: [f32; 3]) -> Transform {
+        Transform {
+            translation: t,
+            ..self
+        }
+    }
+
+    /// Rotation by `angle` radians around `axis`.
+    pub fn rotate(self, axis: [f32; 3], angle: f32) -> Transform {
+        let a = unit(axis);
+        let s = (angle * 0.5).sin();
+        Transform {
+            rotation: [a[0] * s, a[1] * s, a[2] * s, (angle * 0.5).cos()],
+            ..self
+        }
+    }
+
+    pub fn scale(self, s: [f32; 3]) -> Transform {
+        Transform { scale: s, ..self }
+    }
+
+    pub fn to_mat(&self) -> Mat4x4 {
+        let [x, y, z, w] = self.rotation;
+        let rotation = Mat4x4 {
+            mat: [
+                [
+                    1. - 2. * (y * y + z * z),
+                    2. * (x * y - z * w),
+                    2. * (x * z + y * w),
+                    0.,
+                ],
+                [
+                    2. * (x * y + z * w),
+                    1. - 2. * (x * x + z * z),
+                    2. * (y * z - x * w),
+                    0.,
+                ],
+                [
+                    2. * (x * z - y * w),
+                    2. * (y * z + x * w),
+                    1. - 2. * (x * x + y * y),
+                    0.,
+                ],
+                [0., 0., 0., 1.],
+            ],
+        };
+
+        let [tx, ty, tz] = self.translation;
+        let [sx, sy, sz] = self.scale;
+        translation_mat(tx, ty, tz) * rotation * scale_mat(sx, sy, sz)
+    }
+
+    /// Linear interpolation of translation and scale, spherical of rotation.
+    pub fn lerp(&self, other: &Transform, t: f32) -> Transform {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+
+        Transform {
+            translation: [0, 1, 2].map(|i| mix(self.translation[i], other.translation[i])),
+            rotation: slerp(self.rotation, other.rotation, t),
+            scale: [0, 1, 2].map(|i| mix(self.scale[i], other.scale[i])),
+        }
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform::new()
+    }
+}
+
+fn slerp(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    let mut cos = a[0] * b[0] + a[1] * b[1] + a[2] * b[2] + a[3] * b[3];
+    // take the short way around
+    let b = if cos < 0. {
+        cos = -cos;
+        b.map(|c| -c)
+    } else {
+        b
+    };
+
+    let (wa, wb) = if cos > 0.9995 {
+        (1. - t, t)
+    } else {
+        let theta = cos.acos();
+        let sin = theta.sin();
+        (((1. - t) * theta).sin() / sin, (t * theta).sin() / sin)
+    };
+
+    let q = [0, 1, 2, 3].map(|i| a[i] * wa + b[i] * wb);
+    let len = (q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3]).sqrt();
+    q.map(|c| c / len)
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::figure::{Face, Mesh, Obj3D, Vertex};
+use super::material::{load_mtl, Material};
+
+/// Faces sharing an object name and a material.
+struct Group {
+    name: String,
+    material: Option<String>,
+    faces: Vec<[usize; 3]>,
+    uvs: Vec<Option<[usize; 3]>>,
+}
+
+/// Parses a Wavefront OBJ document. Every `o`/`g` and `usemtl` combination
+/// becomes its own object of the mesh, polygons are triangulated as fans.
+pub fn parse_obj(src: &str, materials: &HashMap<String, Material>) -> Result<Mesh, String> {
+    let mut positions: Vec<[f32; 3]> = vec![];
+    let mut texcoords: Vec<[f32; 2]> = vec![];
+    let mut groups: Vec<Group> = vec![];
+    let mut name = String::from("default");
+    let mut material: Option<String> = None;
+
+    for (n, line) in src.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        let key = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+
+        match key {
+            "v" => {
+                let v: Vec<f32> = tokens.filter_map(|t| t.parse().ok()).collect();
+                if v.len() < 3 {
+                    return Err(format!("line {}: vertex needs 3 coordinates", n + 1));
+                }
+                positions.push([v[0], v[1], v[2]]);
+            }
+            "vt" => {
+                let t: Vec<f32> = tokens.filter_map(|t| t.parse().ok()).collect();
+                if t.is_empty() {
+                    return Err(format!("line {}: texture coordinate needs a value", n + 1));
+                }
+                texcoords.push([t[0], t.get(1).copied().unwrap_or(0.)]);
+            }
+            "o" | "g" => {
+                name = tokens.collect::<Vec<&str>>().join(" ");
+            }
+            "usemtl" => {
+                material = tokens.next().map(String::from);
+            }
+            "f" => {
+                let mut polygon = vec![];
+                let mut mapped = vec![];
+                for t in tokens {
+                    let mut parts = t.split('/');
+                    let bad = || format!("line {}: bad face index {}", n + 1, t);
+                    let idx = parts.next().and_then(|i| resolve(i, positions.len()));
+                    polygon.push(idx.ok_or_else(bad)?);
+
+                    if let Some(i) = parts.next().filter(|i| !i.is_empty()) {
+                        mapped.push(resolve(i, texcoords.len()).ok_or_else(bad)?);
+                    }
+                }
+                // only faces with a uv on every corner are mapped
+                let mapped = if mapped.len() == polygon.len() {
+                    Some(mapped)
+                } else {
+                    None
+                };
+
+                let same = |g: &Group| g.name == name && g.material == material;
+                if !groups.last().map(same).unwrap_or(false) {
+                    groups.push(Group {
+                        name: name.clone(),
+                        material: material.clone(),
+                        faces: vec![],
+                        uvs: vec![],
+                    });
+                }
+                let group = groups.last_mut().unwrap();
+                for i in 1..polygon.len().saturating_sub(1) {
+                    group.faces.push([polygon[0], polygon[i], polygon[i + 1]]);
+                    group
+                        .uvs
+                        .push(mapped.as_ref().map(|m| [m[0], m[i], m[i + 1]]));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut mesh = Mesh::new();
+
+    for group in groups {
+        let mut key = group.name.clone();
+        if let Some(m) = group.material.as_ref() {
+            if mesh.objects.contains_key(&key) {
+                key = format!("{}:{}", group.name, m);
+            }
+        }
+        let mut unique = key.clone();
+        let mut i = 1;
+        while mesh.objects.contains_key(&unique) {
+            unique = format!("{}.{}", key, i);
+            i += 1;
+        }
+
+        let obj = mesh.push_object(&unique);
+        append(obj, &positions, &texcoords, &group);
+        if let Some(m) = group.material.as_ref().and_then(|m| materials.get(m)) {
+            obj.set_material(m.clone());
+        }
+    }
+
+    Ok(mesh)
+}
+
+/// Loads an OBJ file together with the MTL libraries it references.
+pub fn load_obj<P: AsRef<Path>>(path: P) -> io::Result<Mesh> {
+    let path = path.as_ref();
+    let src = fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    let mut materials = HashMap::new();
+    for line in src.lines() {
+        if let Some(lib) = line.trim().strip_prefix("mtllib ") {
+            materials.extend(load_mtl(dir.join(lib.trim()))?);
+        }
+    }
+
+    parse_obj(&src, &materials).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// 1 based or negative (relative) OBJ index to a 0 based one.
+fn resolve(token: &str, len: usize) -> Option<usize> {
+    let idx: i64 = token.parse().ok()?;
+    let idx = if idx < 0 { len as i64 + idx } else { idx - 1 };
+    if idx < 0 || idx as usize >= len {
+        return None;
+    }
+    Some(idx as usize)
+}
+
+fn append(obj: &mut Obj3D, positions: &[[f32; 3]], texcoords: &[[f32; 2]], group: &Group) {
+    let faces = &group.faces;
+    let mut local: HashMap<usize, usize> = HashMap::new();
+    let mut order = vec![];
+
+    for face in faces {
+        for v in face {
+            if !local.contains_key(v) {
+                local.insert(*v, order.len());
+                order.push(*v);
+            }
+        }
+    }
+
+    // all vertexes go in before the faces, push_face appends helper vertexes
+    for v in order {
+        obj.push_vertex(Vertex::from_vec(positions[v]));
+    }
+    for (face, uvs) in faces.iter().zip(group.uvs.iter()) {
+        let mut f = Face::new(face.map(|v| local[&v]));
+        if let Some(uvs) = uvs {
+            f = f.with_uvs(uvs.map(|t| texcoords[t]));
+        }
+        obj.push_face(f);
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use crate::model::figure::{Face, Obj3D, Vertex};
+
+use super::coloring::Coloring;
+use super::heightfield::Heightfield;
+
+/// Chunk of the grid at a level of detail, each level halving the samples
+/// per side of the one before.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ChunkKey {
+    pub x: i32,
+    pub z: i32,
+    pub lod: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub key: ChunkKey,
+    /// Corner the field starts at, the chunk covering up to `origin + size`.
+    pub origin: [f32; 2],
+    pub field: Heightfield,
+    /// The field with a skirt hanging down from its border, which covers the
+    /// cracks between neighbours of other levels.
+    pub obj: Obj3D,
+    used: u64,
+}
+
+/// Terrain of unbounded size cut into square chunks, generated when they
+/// come within `radius` chunks of the center and kept until the cache is
+/// full. Farther chunks get coarser grids.
+#[derive(Debug, Clone)]
+pub struct Chunks {
+    /// Length of each side of a chunk.
+    pub size: f32,
+    /// Samples along each side at level 0, best a power of 2 plus 1 so the
+    /// coarser levels share its samples.
+    pub resolution: usize,
+    pub radius: usize,
+    pub lods: usize,
+    /// Distance up to which chunks use level 0, each next level reaching
+    /// twice as far.
+    pub lod_distance: f32,
+    /// How far the skirts hang below the border.
+    pub skirt: f32,
+    /// Chunks generated per update at most, nearest first.
+    pub budget: usize,
+    /// Chunks kept at most, the least recently shown ones dropped first.
+    pub capacity: usize,
+    /// Paints the faces of new chunks.
+    pub coloring: Option<Coloring>,
+    cache: HashMap<ChunkKey, Chunk>,
+    visible: Vec<ChunkKey>,
+    updates: u64,
+}
+
+impl Chunks {
+    pub fn new(size: f32, resolution: usize) -> Chunks {
+        let radius = 3;
+        Chunks {
+            size,
+            resolution: resolution.max(2),
+            radius,
+            lods: 4,
+            lod_distance: 1.5 * size,
+            skirt: 0.1 * size,
+            budget: usize::MAX,
+            capacity: 2 * (2 * radius + 1) * (2 * radius + 1),
+            coloring: None,
+            cache: HashMap::new(),
+            visible: vec![],
+            updates: 0,
+        }
+    }
+
+    /// Also sizes the cache to twice the chunks in view.
+    pub fn with_radius(mut self, radius: usize) -> Chunks {
+        self.radius = radius;
+        self.capacity = 2 * (2 * radius + 1) * (2 * radius + 1);
+        self
+    }
+
+    pub fn with_lods(mut self, lods: usize, lod_distance: f32) -> Chunks {
+        self.lods = lods.max(1);
+        self.lod_distance = lod_distance;
+        self
+    }
+
+    pub fn with_skirt(mut self, skirt: f32) -> Chunks {
+        self.skirt = skirt;
+        self
+    }
+
+    pub fn with_budget(mut self, budget: usize) -> Chunks {
+        self.budget = budget;
+        self
+    }
+
+    pub fn with_coloring(mut self, coloring: Coloring) -> Chunks {
+        self.coloring = Some(coloring);
+        self
+    }
+
+    /// Level of detail of a chunk whose center is `distance` away.
+    pub fn lod(&self, distance: f32) -> usize {
+        let mut lod = 0;
+        let mut reach = self.lod_distance;
+        while distance > reach && lod + 1 < self.lods {
+            lod += 1;
+            reach *= 2.;
+        }
+        lod
+    }
+
+    /// Shows the chunks around `center` at the levels their distance asks
+    /// for, generating missing ones with `height` at each x, z position.
+    /// Chunks past the budget show a cached level of detail until their
+    /// turn comes, or nothing.
+    pub fn update(&mut self, center: [f32; 2], height: impl Fn(f32, f32) -> f32) {
+        self.update_within(center, height, self.budget);
+    }
+
+    /// `update` generating every missing chunk at once.
+    pub fn fill(&mut self, center: [f32; 2], height: impl Fn(f32, f32) -> f32) {
+        self.update_within(center, height, usize::MAX);
+    }
+
+    fn update_within(&mut self, center: [f32; 2], height: impl Fn(f32, f32) -> f32, budget: usize) {
+        self.updates += 1;
+        let r = self.radius as i32;
+        let cx = (center[0] / self.size).floor() as i32;
+        let cz = (center[1] / self.size).floor() as i32;
+
+        let mut wanted = vec![];
+        for z in cz - r..=cz + r {
+            for x in cx - r..=cx + r {
+                let distance = ((x as f32 + 0.5) * self.size - center[0])
+                    .hypot((z as f32 + 0.5) * self.size - center[1]);
+                let lod = self.lod(distance);
+                wanted.push((distance, ChunkKey { x, z, lod }));
+            }
+        }
+        wanted.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        self.visible.clear();
+        let mut generated = 0;
+        for (_, key) in wanted {
+            let key = if self.cache.contains_key(&key) {
+                Some(key)
+            } else if generated < budget {
+                generated += 1;
+                let chunk = self.generate(key, &height);
+                self.cache.insert(key, chunk);
+                Some(key)
+            } else {
+                (0..self.lods)
+                    .map(|lod| ChunkKey { lod, ..key })
+                    .find(|k| self.cache.contains_key(k))
+            };
+            if let Some(key) = key {
+                if let Some(chunk) = self.cache.get_mut(&key) {
+                    chunk.used = self.updates;
+                }
+                self.visible.push(key);
+            }
+        }
+
+        if self.cache.len() > self.capacity {
+            let mut keys: Vec<(u64, ChunkKey)> =
+                self.cache.values().map(|c| (c.used, c.key)).collect();
+            keys.sort_by_key(|(used, _)| *used);
+            for (_, key) in keys.iter().take(self.cache.len() - self.capacity) {
+                self.cache.remove(key);
+            }
+        }
+    }
+
+    /// Chunks shown by the last update, nearest first.
+    pub fn visible(&self) -> impl Iterator<Item = &Chunk> {
+        self.visible.iter().filter_map(|key| self.cache.get(key))
+    }
+
+    /// Drops every chunk, for when the heights change.
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        self.visible.clear();
+    }
+
+    fn generate(&self, key: ChunkKey, height: &impl Fn(f32, f32) -> f32) -> Chunk {
+        let origin = [key.x as f32 * self.size, key.z as f32 * self.size];
+        let samples = ((self.resolution - 1) >> key.lod).max(1) + 1;
+        let field = Heightfield::from_fn([samples; 2], [self.size; 2], |x, z| {
+            height(origin[0] + x, origin[1] + z)
+        });
+        let mut obj = skirted(&field, self.skirt);
+        if let Some(coloring) = &self.coloring {
+            coloring.paint(&field, &mut obj);
+        }
+        Chunk {
+            key,
+            origin,
+            field,
+            obj,
+            used: self.updates,
+        }
+    }
+}
+
+/// The field's triangles and a wall of faces `depth` deep below its border,
+/// facing out. The wall has no edges.
+fn skirted(field: &Heightfield, depth: f32) -> Obj3D {
+    let [w, h] = field.resolution;
+    let mut obj = field.to_obj();
+
+    // around the border, with the outside on the same side of every step
+    let border: Vec<(usize, usize)> = (0..w - 1)
+        .map(|x| (x, 0))
+        .chain((0..h - 1).map(|z| (w - 1, z)))
+        .chain((1..w).rev().map(|x| (x, h - 1)))
+        .chain((1..h).rev().map(|z| (0, z)))
+        .collect();
+
+    let bottom = obj.vertexes.len();
+    for &(x, z) in border.iter() {
+        let [px, py, pz] = field.position(x, z);
+        obj.push_vertex(Vertex::from_vec([px, py - depth, pz]));
+    }
+    for i in 0..border.len() {
+        let j = (i + 1) % border.len();
+        let (a, b) = (border[i], border[j]);
+        let (a, b) = (a.1 * w + a.0, b.1 * w + b.0);
+        obj.push_face(Face::new([a, b, bottom + i]));
+        obj.push_face(Face::new([bottom + i, b, bottom + j]));
+    }
+    obj
+}
@@ -0,0 +1,121 @@
+use crate::model::figure::Obj3D;
+use crate::model::material::Material;
+
+use super::heightfield::Heightfield;
+
+/// Color the ground takes from a height up.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Band {
+    pub height: f32,
+    /// Linear color.
+    pub color: [f32; 3],
+}
+
+/// Colors of the ground by height band, with rock showing on steep slopes.
+#[derive(Debug, Clone)]
+pub struct Coloring {
+    /// From the lowest up. The first band covers everything below the
+    /// second one.
+    pub bands: Vec<Band>,
+    /// Height over which neighbour bands blend into each other.
+    pub blend: f32,
+    pub rock: [f32; 3],
+    /// Slopes, as rise over run, where rock starts to show and where it
+    /// covers the ground.
+    pub slope: [f32; 2],
+}
+
+impl Coloring {
+    /// Water, sand, grass, rock and snow spread over the heights from `low`
+    /// to `high`.
+    pub fn new(low: f32, high: f32) -> Coloring {
+        let range = high - low;
+        let band = |t: f32, color| Band {
+            height: low + t * range,
+            color,
+        };
+        Coloring {
+            bands: vec![
+                band(0., [0.02, 0.09, 0.25]),
+                band(0.38, [0.55, 0.45, 0.22]),
+                band(0.44, [0.08, 0.25, 0.04]),
+                band(0.72, [0.2, 0.17, 0.14]),
+                band(0.86, [0.85, 0.87, 0.9]),
+            ],
+            blend: 0.04 * range,
+            rock: [0.2, 0.17, 0.14],
+            slope: [0.6, 1.2],
+        }
+    }
+
+    pub fn with_bands(mut self, bands: Vec<Band>) -> Coloring {
+        self.bands = bands;
+        self
+    }
+
+    pub fn with_rock(mut self, rock: [f32; 3], slope: [f32; 2]) -> Coloring {
+        self.rock = rock;
+        self.slope = slope;
+        self
+    }
+
+    /// Color of the ground at `height` with the unit `normal`.
+    pub fn color(&self, height: f32, normal: [f32; 3]) -> [f32; 3] {
+        let mut color = self.bands.first().map_or(self.rock, |b| b.color);
+        for band in self.bands.iter().skip(1) {
+            color = mix(color, band.color, self.cover(band.height, height));
+        }
+
+        let run = normal[1].max(1e-6);
+        let rise = (1. - run * run).max(0.).sqrt();
+        let rock = smoothstep(self.slope[0], self.slope[1], rise / run);
+        mix(color, self.rock, rock)
+    }
+
+    /// Sets the corner colors of the faces of `obj`, whose first vertexes
+    /// are the samples of `field` as `Heightfield::to_obj` lays them out.
+    /// Corners past the samples, like those of skirts, take the color of a
+    /// corner of the same face that is a sample. The material turns white so
+    /// the colors show as they are.
+    pub fn paint(&self, field: &Heightfield, obj: &mut Obj3D) {
+        let [w, h] = field.resolution;
+        let mut colors = Vec::with_capacity(w * h);
+        for z in 0..h {
+            for x in 0..w {
+                colors.push(self.color(field.height(x, z), field.normal(x, z)));
+            }
+        }
+
+        for face in obj.faces.iter_mut() {
+            let fallback = face
+                .vertexes
+                .iter()
+                .find_map(|v| colors.get(*v))
+                .copied()
+                .unwrap_or([1., 1., 1.]);
+            face.colors = Some(
+                face.vertexes
+                    .map(|v| colors.get(v).copied().unwrap_or(fallback)),
+            );
+        }
+        obj.set_material(Material::lambertian([1., 1., 1.]));
+    }
+
+    /// How much a band starting at `start` covers the ground at `height`.
+    fn cover(&self, start: f32, height: f32) -> f32 {
+        let half = self.blend * 0.5;
+        smoothstep(start - half, start + half, height)
+    }
+}
+
+fn mix(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge1 <= edge0 {
+        return if x < edge0 { 0. } else { 1. };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
@@ -0,0 +1,250 @@
+use crate::model::rng::Rng;
+
+use super::heightfield::Heightfield;
+
+/// Droplets running down the field, picking up sediment where they speed up
+/// and dropping it where they slow down or climb, which carves channels.
+/// Positions are in grid cells and heights in the units of the field.
+#[derive(Debug, Copy, Clone)]
+pub struct Hydraulic {
+    pub droplets: usize,
+    pub seed: u64,
+    /// Steps a droplet runs for at most.
+    pub lifetime: usize,
+    /// Part of the previous direction a droplet keeps at each step, in
+    /// [0, 1]. 0 follows the slope exactly.
+    pub inertia: f32,
+    /// Sediment carried per unit of drop, speed and water.
+    pub capacity: f32,
+    /// Drop the capacity is computed with on flat ground.
+    pub min_slope: f32,
+    /// Part of the spare capacity taken from the ground each step.
+    pub erosion: f32,
+    /// Part of the sediment over capacity left on the ground each step.
+    pub deposition: f32,
+    /// Part of the water lost each step.
+    pub evaporation: f32,
+    pub gravity: f32,
+    /// Cells around a droplet the ground is taken from, which smooths the
+    /// channels.
+    pub radius: usize,
+}
+
+impl Hydraulic {
+    pub fn new(droplets: usize, seed: u64) -> Hydraulic {
+        Hydraulic {
+            droplets,
+            seed,
+            lifetime: 30,
+            inertia: 0.05,
+            capacity: 4.,
+            min_slope: 0.01,
+            erosion: 0.3,
+            deposition: 0.3,
+            evaporation: 0.01,
+            gravity: 4.,
+            radius: 3,
+        }
+    }
+
+    pub fn apply(&self, field: &mut Heightfield) {
+        let [w, h] = field.resolution;
+        let mut rng = Rng::new(self.seed);
+
+        // cells within the radius, nearer ones weighted more
+        let r = self.radius as isize;
+        let mut brush = vec![];
+        for dz in -r..=r {
+            for dx in -r..=r {
+                let weight = self.radius as f32 - ((dx * dx + dz * dz) as f32).sqrt();
+                if weight > 0. || r == 0 {
+                    brush.push((dx, dz, weight.max(1e-3)));
+                }
+            }
+        }
+
+        for _ in 0..self.droplets {
+            let mut pos = [
+                rng.next_f32() * (w - 1) as f32,
+                rng.next_f32() * (h - 1) as f32,
+            ];
+            let mut dir = [0., 0.];
+            let (mut speed, mut water, mut sediment) = (1., 1., 0.);
+
+            for _ in 0..self.lifetime {
+                let cell = [pos[0] as usize, pos[1] as usize];
+                let (height, gradient) = height_gradient(field, pos);
+
+                dir = [0, 1].map(|i| dir[i] * self.inertia - gradient[i] * (1. - self.inertia));
+                let len = dir[0].hypot(dir[1]);
+                if len < 1e-9 {
+                    break;
+                }
+                dir = dir.map(|d| d / len);
+                let old = pos;
+                pos = [pos[0] + dir[0], pos[1] + dir[1]];
+                if pos[0] < 0.
+                    || pos[1] < 0.
+                    || pos[0] >= (w - 1) as f32
+                    || pos[1] >= (h - 1) as f32
+                {
+                    break;
+                }
+
+                let drop = height_gradient(field, pos).0 - height;
+                let capacity = (-drop).max(self.min_slope) * speed * water * self.capacity;
+
+                if sediment > capacity || drop > 0. {
+                    // uphill fills the pit behind, at most up to the new height
+                    let amount = if drop > 0. {
+                        drop.min(sediment)
+                    } else {
+                        (sediment - capacity) * self.deposition
+                    };
+                    sediment -= amount;
+                    deposit(field, cell, old, amount);
+                } else {
+                    let amount = ((capacity - sediment) * self.erosion).min(-drop);
+                    sediment += amount;
+                    erode(field, cell, &brush, amount);
+                }
+
+                speed = (speed * speed - drop * self.gravity).max(0.).sqrt();
+                water *= 1. - self.evaporation;
+            }
+        }
+    }
+}
+
+/// Ground slipping off slopes steeper than the talus angle onto its lower
+/// neighbours, like loose rock piling up at the foot of cliffs.
+#[derive(Debug, Copy, Clone)]
+pub struct Thermal {
+    pub iterations: usize,
+    /// Tangent of the steepest stable slope.
+    pub talus: f32,
+    /// Part of the excess moved each iteration, in (0, 1].
+    pub rate: f32,
+}
+
+impl Thermal {
+    pub fn new(iterations: usize) -> Thermal {
+        Thermal {
+            iterations,
+            talus: 0.7,
+            rate: 0.5,
+        }
+    }
+
+    pub fn apply(&self, field: &mut Heightfield) {
+        let [w, h] = field.resolution;
+        let [dx, dz] = field.spacing();
+        let neighbours: Vec<(isize, isize, f32)> = [
+            (-1, -1),
+            (0, -1),
+            (1, -1),
+            (-1, 0),
+            (1, 0),
+            (-1, 1),
+            (0, 1),
+            (1, 1),
+        ]
+        .into_iter()
+        .map(|(x, z)| (x, z, (x as f32 * dx).hypot(z as f32 * dz)))
+        .collect();
+
+        let mut delta = vec![0.; field.heights.len()];
+        let mut excess = Vec::with_capacity(8);
+        for _ in 0..self.iterations {
+            delta.fill(0.);
+            for z in 0..h {
+                for x in 0..w {
+                    let height = field.height(x, z);
+                    excess.clear();
+                    for (nx, nz, distance) in neighbours.iter() {
+                        let (nx, nz) = (x as isize + nx, z as isize + nz);
+                        if nx < 0 || nz < 0 || nx >= w as isize || nz >= h as isize {
+                            continue;
+                        }
+                        let n = nz as usize * w + nx as usize;
+                        let e = height - field.heights[n] - self.talus * distance;
+                        if e > 0. {
+                            excess.push((n, e));
+                        }
+                    }
+
+                    // half the largest excess levels the steepest pair, shared
+                    // by the neighbours in proportion to their own excess
+                    let total: f32 = excess.iter().map(|(_, e)| e).sum();
+                    let largest = excess.iter().map(|(_, e)| *e).fold(0., f32::max);
+                    let moved = self.rate * largest * 0.5;
+                    for (n, e) in excess.iter() {
+                        delta[*n] += moved * e / total;
+                    }
+                    if !excess.is_empty() {
+                        delta[z * w + x] -= moved;
+                    }
+                }
+            }
+            for (height, d) in field.heights.iter_mut().zip(delta.iter()) {
+                *height += d;
+            }
+        }
+    }
+}
+
+/// Bilinear height and its slope along x and z per cell at a position in
+/// cells, which must be inside the grid.
+fn height_gradient(field: &Heightfield, pos: [f32; 2]) -> (f32, [f32; 2]) {
+    let (x, z) = (pos[0] as usize, pos[1] as usize);
+    let (u, v) = (pos[0] - x as f32, pos[1] - z as f32);
+    let h00 = field.height(x, z);
+    let h10 = field.height(x + 1, z);
+    let h01 = field.height(x, z + 1);
+    let h11 = field.height(x + 1, z + 1);
+
+    let height = h00 * (1. - u) * (1. - v) + h10 * u * (1. - v) + h01 * (1. - u) * v + h11 * u * v;
+    let gradient = [
+        (h10 - h00) * (1. - v) + (h11 - h01) * v,
+        (h01 - h00) * (1. - u) + (h11 - h10) * u,
+    ];
+    (height, gradient)
+}
+
+/// Adds `amount` to the corners of the cell, weighted by how close `pos`
+/// is to each.
+fn deposit(field: &mut Heightfield, cell: [usize; 2], pos: [f32; 2], amount: f32) {
+    let [x, z] = cell;
+    let (u, v) = (pos[0] - x as f32, pos[1] - z as f32);
+    for (cx, cz, weight) in [
+        (x, z, (1. - u) * (1. - v)),
+        (x + 1, z, u * (1. - v)),
+        (x, z + 1, (1. - u) * v),
+        (x + 1, z + 1, u * v),
+    ] {
+        let height = field.height(cx, cz);
+        field.set_height(cx, cz, height + amount * weight);
+    }
+}
+
+/// Removes `amount` from the cells under the brush around `cell`.
+fn erode(field: &mut Heightfield, cell: [usize; 2], brush: &[(isize, isize, f32)], amount: f32) {
+    let [w, h] = field.resolution.map(|n| n as isize);
+    let inside = |(dx, dz, _): &&(isize, isize, f32)| {
+        let (x, z) = (cell[0] as isize + dx, cell[1] as isize + dz);
+        x >= 0 && z >= 0 && x < w && z < h
+    };
+    let total: f32 = brush
+        .iter()
+        .filter(inside)
+        .map(|(_, _, weight)| weight)
+        .sum();
+    for (dx, dz, weight) in brush.iter().filter(inside) {
+        let (x, z) = (
+            (cell[0] as isize + dx) as usize,
+            (cell[1] as isize + dz) as usize,
+        );
+        let height = field.height(x, z);
+        field.set_height(x, z, height - amount * weight / total);
+    }
+}
@@ -0,0 +1,375 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use noise::{NoiseFn, OpenSimplex, Perlin, Simplex, SuperSimplex, Value, Worley};
+use serde::Deserialize;
+
+/// Noise over the plane giving values about [-1, 1].
+pub type Noise = Box<dyn NoiseFn<f64, 2>>;
+
+/// Single octave noise a recipe starts from.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BaseNoise {
+    #[default]
+    Perlin,
+    Simplex,
+    OpenSimplex,
+    SuperSimplex,
+    Value,
+    Worley,
+}
+
+impl BaseNoise {
+    pub fn build(self, seed: u32) -> Noise {
+        match self {
+            BaseNoise::Perlin => Box::new(Perlin::new(seed)),
+            BaseNoise::Simplex => Box::new(Simplex::new(seed)),
+            BaseNoise::OpenSimplex => Box::new(OpenSimplex::new(seed)),
+            BaseNoise::SuperSimplex => Box::new(SuperSimplex::new(seed)),
+            BaseNoise::Value => Box::new(Value::new(seed)),
+            BaseNoise::Worley => Box::new(Worley::new(seed)),
+        }
+    }
+}
+
+impl FromStr for BaseNoise {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BaseNoise, String> {
+        match s {
+            "perlin" => Ok(BaseNoise::Perlin),
+            "simplex" => Ok(BaseNoise::Simplex),
+            "open-simplex" => Ok(BaseNoise::OpenSimplex),
+            "super-simplex" => Ok(BaseNoise::SuperSimplex),
+            "value" => Ok(BaseNoise::Value),
+            "worley" => Ok(BaseNoise::Worley),
+            _ => Err(format!("unknown noise {}", s)),
+        }
+    }
+}
+
+/// Step of a recipe, each wraps the noise built by the steps before it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Stage {
+    /// Sum of `octaves` copies, each `lacunarity` times finer and `gain`
+    /// times weaker than the previous one.
+    Fbm {
+        #[serde(default = "octaves")]
+        octaves: u32,
+        #[serde(default = "lacunarity")]
+        lacunarity: f64,
+        #[serde(default = "gain")]
+        gain: f64,
+    },
+    /// Fbm of the inverted absolute value, sharp crests where the noise
+    /// crosses 0. Finer octaves only show up near the crests.
+    Ridged {
+        #[serde(default = "octaves")]
+        octaves: u32,
+        #[serde(default = "lacunarity")]
+        lacunarity: f64,
+        #[serde(default = "gain")]
+        gain: f64,
+    },
+    /// Fbm of the absolute value, round hills between sharp valleys.
+    Billow {
+        #[serde(default = "octaves")]
+        octaves: u32,
+        #[serde(default = "lacunarity")]
+        lacunarity: f64,
+        #[serde(default = "gain")]
+        gain: f64,
+    },
+    /// Moves the input by up to `strength` along a Perlin noise read
+    /// `frequency` times per input unit.
+    Warp {
+        #[serde(default = "half")]
+        strength: f64,
+        #[serde(default = "one")]
+        frequency: f64,
+    },
+    /// Flattens the values into `steps` levels. `sharpness` 1 leaves them
+    /// unchanged, larger values give flatter steps and steeper risers.
+    Terrace {
+        #[serde(default = "steps")]
+        steps: u32,
+        #[serde(default = "sharpness")]
+        sharpness: f64,
+    },
+    /// Maps the values through the line joining the `[input, output]`
+    /// points, sorted by input and held flat past the ends.
+    Curve { points: Vec<[f64; 2]> },
+    /// `value * scale + bias`.
+    Scale {
+        #[serde(default = "one")]
+        scale: f64,
+        #[serde(default)]
+        bias: f64,
+    },
+    /// Combines the values with those of another recipe.
+    Combine { op: Op, with: Box<Recipe> },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Add,
+    Multiply,
+    Min,
+    Max,
+}
+
+/// Noise built from a base noise by a pipeline of stages, for example
+/// `{"noise": "perlin", "seed": 3, "stages": [{"type": "warp"},
+/// {"type": "ridged", "octaves": 5}, {"type": "terrace", "steps": 6}]}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Recipe {
+    #[serde(default)]
+    pub noise: BaseNoise,
+    #[serde(default)]
+    pub seed: u32,
+    #[serde(default)]
+    pub stages: Vec<Stage>,
+}
+
+impl Recipe {
+    /// The base noise alone.
+    pub fn new(noise: BaseNoise, seed: u32) -> Recipe {
+        Recipe {
+            noise,
+            seed,
+            stages: vec![],
+        }
+    }
+
+    pub fn with_stage(mut self, stage: Stage) -> Recipe {
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn build(&self) -> Noise {
+        let mut noise = self.noise.build(self.seed);
+        for (i, stage) in self.stages.iter().enumerate() {
+            noise = match stage.clone() {
+                Stage::Fbm {
+                    octaves,
+                    lacunarity,
+                    gain,
+                } => Box::new(Fractal {
+                    source: noise,
+                    kind: Kind::Fbm,
+                    octaves,
+                    lacunarity,
+                    gain,
+                }),
+                Stage::Ridged {
+                    octaves,
+                    lacunarity,
+                    gain,
+                } => Box::new(Fractal {
+                    source: noise,
+                    kind: Kind::Ridged,
+                    octaves,
+                    lacunarity,
+                    gain,
+                }),
+                Stage::Billow {
+                    octaves,
+                    lacunarity,
+                    gain,
+                } => Box::new(Fractal {
+                    source: noise,
+                    kind: Kind::Billow,
+                    octaves,
+                    lacunarity,
+                    gain,
+                }),
+                Stage::Warp {
+                    strength,
+                    frequency,
+                } => Box::new(Warp {
+                    source: noise,
+                    // a seed of its own per warp stage
+                    perlin: Perlin::new(self.seed.wrapping_add(i as u32 + 1)),
+                    strength,
+                    frequency,
+                }),
+                Stage::Terrace { steps, sharpness } => {
+                    let steps = steps.max(1) as f64;
+                    map(noise, move |v| {
+                        let t = (v * 0.5 + 0.5) * steps;
+                        let level = t.floor();
+                        let rise = (t - level).powf(sharpness.max(1.));
+                        (level + rise) / steps * 2. - 1.
+                    })
+                }
+                Stage::Curve { mut points } => {
+                    points.sort_by(|a, b| a[0].total_cmp(&b[0]));
+                    map(noise, move |v| curve(&points, v))
+                }
+                Stage::Scale { scale, bias } => map(noise, move |v| v * scale + bias),
+                Stage::Combine { op, with } => Box::new(Combine {
+                    a: noise,
+                    b: with.build(),
+                    op,
+                }),
+            };
+        }
+        noise
+    }
+}
+
+pub fn parse_recipe(src: &str) -> Result<Recipe, String> {
+    serde_json::from_str(src).map_err(|e| e.to_string())
+}
+
+pub fn load_recipe<P: AsRef<Path>>(path: P) -> io::Result<Recipe> {
+    let src = fs::read_to_string(path)?;
+    parse_recipe(&src).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Kind {
+    Fbm,
+    Ridged,
+    Billow,
+}
+
+struct Fractal {
+    source: Noise,
+    kind: Kind,
+    octaves: u32,
+    lacunarity: f64,
+    gain: f64,
+}
+
+impl NoiseFn<f64, 2> for Fractal {
+    fn get(&self, p: [f64; 2]) -> f64 {
+        let (mut sum, mut total) = (0., 0.);
+        let (mut amplitude, mut frequency) = (1., 1.);
+        // ridged octaves are weighted by the ones before
+        let mut weight = 1.;
+        for i in 0..self.octaves.max(1) {
+            // shifted so the octaves do not all meet at the origin
+            let shift = i as f64 * 17.31;
+            let n = self
+                .source
+                .get([p[0] * frequency + shift, p[1] * frequency - shift]);
+            let v = match self.kind {
+                Kind::Fbm => n,
+                Kind::Billow => 2. * n.abs() - 1.,
+                Kind::Ridged => {
+                    let ridge = (1. - n.abs()).powi(2) * weight;
+                    weight = (ridge * 2.).clamp(0., 1.);
+                    2. * ridge - 1.
+                }
+            };
+            sum += v * amplitude;
+            total += amplitude;
+            amplitude *= self.gain;
+            frequency *= self.lacunarity;
+        }
+        sum / total
+    }
+}
+
+struct Warp {
+    source: Noise,
+    perlin: Perlin,
+    strength: f64,
+    frequency: f64,
+}
+
+impl NoiseFn<f64, 2> for Warp {
+    fn get(&self, p: [f64; 2]) -> f64 {
+        let q = [p[0] * self.frequency, p[1] * self.frequency];
+        // two far apart reads of the same noise for the two axes
+        let dx = self.perlin.get([q[0] + 5.2, q[1] + 1.3]);
+        let dz = self.perlin.get([q[0] - 8.3, q[1] + 2.8]);
+        self.source
+            .get([p[0] + self.strength * dx, p[1] + self.strength * dz])
+    }
+}
+
+struct Combine {
+    a: Noise,
+    b: Noise,
+    op: Op,
+}
+
+impl NoiseFn<f64, 2> for Combine {
+    fn get(&self, p: [f64; 2]) -> f64 {
+        let (a, b) = (self.a.get(p), self.b.get(p));
+        match self.op {
+            Op::Add => a + b,
+            Op::Multiply => a * b,
+            Op::Min => a.min(b),
+            Op::Max => a.max(b),
+        }
+    }
+}
+
+struct Map<F> {
+    source: Noise,
+    f: F,
+}
+
+impl<F: Fn(f64) -> f64> NoiseFn<f64, 2> for Map<F> {
+    fn get(&self, p: [f64; 2]) -> f64 {
+        (self.f)(self.source.get(p))
+    }
+}
+
+fn map(source: Noise, f: impl Fn(f64) -> f64 + 'static) -> Noise {
+    Box::new(Map { source, f })
+}
+
+/// Piecewise linear through `points` sorted by input.
+fn curve(points: &[[f64; 2]], v: f64) -> f64 {
+    match points {
+        [] => v,
+        [first, ..] if v <= first[0] => first[1],
+        [.., last] if v >= last[0] => last[1],
+        _ => {
+            let i = points.partition_point(|p| p[0] <= v);
+            let ([x0, y0], [x1, y1]) = (points[i - 1], points[i]);
+            if x1 > x0 {
+                y0 + (y1 - y0) * (v - x0) / (x1 - x0)
+            } else {
+                y1
+            }
+        }
+    }
+}
+
+fn octaves() -> u32 {
+    6
+}
+
+fn lacunarity() -> f64 {
+    2.
+}
+
+fn gain() -> f64 {
+    0.5
+}
+
+fn steps() -> u32 {
+    8
+}
+
+fn sharpness() -> f64 {
+    3.
+}
+
+fn half() -> f64 {
+    0.5
+}
+
+fn one() -> f64 {
+    1.
+}
@@ -0,0 +1,147 @@
+use noise::NoiseFn;
+
+use crate::model::figure::{Edge, Face, Obj3D, Vertex};
+use crate::model::mat::unit;
+
+/// Grid of heights over the x, z plane, starting at the origin and growing
+/// towards +x and +z.
+#[derive(Debug, Clone)]
+pub struct Heightfield {
+    /// Samples along x and z, at least 2 each.
+    pub resolution: [usize; 2],
+    /// Size along x and z.
+    pub extent: [f32; 2],
+    /// Row major, one row of `resolution[0]` heights per z.
+    pub heights: Vec<f32>,
+}
+
+impl Heightfield {
+    /// Flat field at height 0.
+    pub fn new(resolution: [usize; 2], extent: [f32; 2]) -> Heightfield {
+        let resolution = resolution.map(|n| n.max(2));
+        Heightfield {
+            resolution,
+            extent,
+            heights: vec![0.; resolution[0] * resolution[1]],
+        }
+    }
+
+    /// Field with the heights `f` gives at the x, z position of each sample.
+    pub fn from_fn(
+        resolution: [usize; 2],
+        extent: [f32; 2],
+        f: impl Fn(f32, f32) -> f32,
+    ) -> Heightfield {
+        let mut field = Heightfield::new(resolution, extent);
+        let [dx, dz] = field.spacing();
+        for z in 0..field.resolution[1] {
+            for x in 0..field.resolution[0] {
+                field.set_height(x, z, f(x as f32 * dx, z as f32 * dz));
+            }
+        }
+        field
+    }
+
+    /// `amplitude` times `noise`, which gives values in [-1, 1], read
+    /// `frequency` times per unit at the sample positions moved by `offset`.
+    pub fn from_noise(
+        resolution: [usize; 2],
+        extent: [f32; 2],
+        noise: &impl NoiseFn<f64, 2>,
+        frequency: f32,
+        amplitude: f32,
+        offset: [f32; 2],
+    ) -> Heightfield {
+        Heightfield::from_fn(resolution, extent, |x, z| {
+            let p = [
+                ((x + offset[0]) * frequency) as f64,
+                ((z + offset[1]) * frequency) as f64,
+            ];
+            amplitude * noise.get(p) as f32
+        })
+    }
+
+    /// Distance between neighbour samples along x and z.
+    pub fn spacing(&self) -> [f32; 2] {
+        [0, 1].map(|i| self.extent[i] / (self.resolution[i] - 1) as f32)
+    }
+
+    pub fn height(&self, x: usize, z: usize) -> f32 {
+        self.heights[z * self.resolution[0] + x]
+    }
+
+    pub fn set_height(&mut self, x: usize, z: usize, height: f32) {
+        self.heights[z * self.resolution[0] + x] = height;
+    }
+
+    pub fn position(&self, x: usize, z: usize) -> [f32; 3] {
+        let [dx, dz] = self.spacing();
+        [x as f32 * dx, self.height(x, z), z as f32 * dz]
+    }
+
+    /// Bilinear height at the x, z position, clamped to the grid.
+    pub fn sample(&self, x: f32, z: f32) -> f32 {
+        let [dx, dz] = self.spacing();
+        let [w, h] = self.resolution;
+        let fx = (x / dx).clamp(0., (w - 1) as f32);
+        let fz = (z / dz).clamp(0., (h - 1) as f32);
+        let (x0, z0) = ((fx as usize).min(w - 2), (fz as usize).min(h - 2));
+        let (tx, tz) = (fx - x0 as f32, fz - z0 as f32);
+
+        let top = self.height(x0, z0) * (1. - tx) + self.height(x0 + 1, z0) * tx;
+        let bottom = self.height(x0, z0 + 1) * (1. - tx) + self.height(x0 + 1, z0 + 1) * tx;
+        top * (1. - tz) + bottom * tz
+    }
+
+    /// Unit normal at a sample from the slopes towards its neighbours.
+    pub fn normal(&self, x: usize, z: usize) -> [f32; 3] {
+        let [dx, dz] = self.spacing();
+        let [w, h] = self.resolution;
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(w - 1));
+        let (z0, z1) = (z.saturating_sub(1), (z + 1).min(h - 1));
+        let slope_x = (self.height(x1, z) - self.height(x0, z)) / ((x1 - x0) as f32 * dx);
+        let slope_z = (self.height(x, z1) - self.height(x, z0)) / ((z1 - z0) as f32 * dz);
+        unit([-slope_x, 1., -slope_z])
+    }
+
+    /// Two upward facing triangles per grid cell, split along the diagonal
+    /// from (x + 1, z) to (x, z + 1), with the grid lines and diagonals as
+    /// edges. v grows towards -z.
+    pub fn to_obj(&self) -> Obj3D {
+        let [w, h] = self.resolution;
+        let index = |x: usize, z: usize| z * w + x;
+        let uv = |x: usize, z: usize| [x as f32 / (w - 1) as f32, 1. - z as f32 / (h - 1) as f32];
+
+        let mut obj = Obj3D::new();
+        for z in 0..h {
+            for x in 0..w {
+                obj.push_vertex(Vertex::from_vec(self.position(x, z)));
+            }
+        }
+
+        for z in 0..h - 1 {
+            for x in 0..w - 1 {
+                let (a, b, c, d) = ((x, z), (x, z + 1), (x + 1, z), (x + 1, z + 1));
+                for [p, q, r] in [[a, b, c], [c, b, d]] {
+                    let face = Face::new([p, q, r].map(|(x, z)| index(x, z)));
+                    obj.push_face(face.with_uvs([p, q, r].map(|(x, z)| uv(x, z))));
+                }
+            }
+        }
+
+        for z in 0..h {
+            for x in 0..w {
+                if x < w - 1 {
+                    obj.push_edge(Edge::new(index(x, z), index(x + 1, z)));
+                }
+                if z < h - 1 {
+                    obj.push_edge(Edge::new(index(x, z), index(x, z + 1)));
+                }
+                if x < w - 1 && z < h - 1 {
+                    obj.push_edge(Edge::new(index(x + 1, z), index(x, z + 1)));
+                }
+            }
+        }
+        obj
+    }
+}
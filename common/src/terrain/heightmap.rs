@@ -0,0 +1,158 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use nannou::image::{self, ImageBuffer, Luma};
+
+use super::heightfield::Heightfield;
+
+/// Bits per pixel of PNG heightmaps.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Depth {
+    Eight,
+    Sixteen,
+}
+
+/// Loads a grayscale PNG, or a raw `.r32` file of square little endian
+/// floats. Heights are `base + value * scale`, PNG values running from 0 to
+/// 1. Image rows follow z and the field spans `extent`.
+pub fn load_heightmap<P: AsRef<Path>>(
+    path: P,
+    extent: [f32; 2],
+    scale: f32,
+    base: f32,
+) -> io::Result<Heightfield> {
+    let path = path.as_ref();
+    let (resolution, values) = if is_raw(path) {
+        let bytes = fs::read(path)?;
+        let values: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        let side = (values.len() as f64).sqrt() as usize;
+        if bytes.len() % 4 != 0 || side < 2 || side * side != values.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "raw heightmaps hold a square of at least 2 by 2 floats",
+            ));
+        }
+        ([side, side], values)
+    } else {
+        let img = image::open(path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            .to_luma16();
+        let (width, height) = img.dimensions();
+        if width < 2 || height < 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "heightmaps are at least 2 by 2 pixels",
+            ));
+        }
+        let values = img.pixels().map(|p| p[0] as f32 / 65535.).collect();
+        ([width as usize, height as usize], values)
+    };
+
+    let mut field = Heightfield::new(resolution, extent);
+    for (height, value) in field.heights.iter_mut().zip(values) {
+        *height = base + value * scale;
+    }
+    Ok(field)
+}
+
+/// Writes the field as `load_heightmap` reads it, PNG values clamped to
+/// [0, 1] and stored with `depth` bits. Raw files need a square field.
+pub fn save_heightmap<P: AsRef<Path>>(
+    field: &Heightfield,
+    path: P,
+    scale: f32,
+    base: f32,
+    depth: Depth,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let values = field.heights.iter().map(|h| (h - base) / scale);
+    if is_raw(path) {
+        if field.resolution[0] != field.resolution[1] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "raw heightmaps hold square fields only",
+            ));
+        }
+        let bytes: Vec<u8> = values.flat_map(|v| v.to_le_bytes()).collect();
+        return fs::write(path, bytes);
+    }
+
+    let [width, height] = field.resolution.map(|n| n as u32);
+    let result = match depth {
+        Depth::Eight => {
+            let data = values
+                .map(|v| (v.clamp(0., 1.) * 255. + 0.5) as u8)
+                .collect();
+            ImageBuffer::<Luma<u8>, Vec<u8>>::from_raw(width, height, data)
+                .map(|img| img.save(path))
+        }
+        Depth::Sixteen => {
+            let data = values
+                .map(|v| (v.clamp(0., 1.) * 65535. + 0.5) as u16)
+                .collect();
+            ImageBuffer::<Luma<u16>, Vec<u16>>::from_raw(width, height, data)
+                .map(|img| img.save(path))
+        }
+    };
+    match result {
+        Some(Ok(())) => Ok(()),
+        Some(Err(e)) => Err(io::Error::other(e)),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "heights do not match the resolution",
+        )),
+    }
+}
+
+fn is_raw(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("r32"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Saves a field and loads it back, returning the largest height error.
+    fn round_trip(file: &str, depth: Depth) -> f32 {
+        let (scale, base) = (2., -0.5);
+        let mut field = Heightfield::new([4, 4], [1., 1.]);
+        for (i, height) in field.heights.iter_mut().enumerate() {
+            // exact in binary through the scale and base
+            *height = base + i as f32 / 8.;
+        }
+
+        let path = std::env::temp_dir().join(format!("heightmap-{}-{}", std::process::id(), file));
+        save_heightmap(&field, &path, scale, base, depth).unwrap();
+        let loaded = load_heightmap(&path, field.extent, scale, base).unwrap();
+        fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.resolution, field.resolution);
+        field
+            .heights
+            .iter()
+            .zip(loaded.heights.iter())
+            .map(|(a, b)| (a - b).abs())
+            .fold(0., f32::max)
+    }
+
+    #[test]
+    fn eight_bit_png_round_trips() {
+        assert!(round_trip("eight.png", Depth::Eight) <= 2. / 255.);
+    }
+
+    #[test]
+    fn sixteen_bit_png_round_trips() {
+        assert!(round_trip("sixteen.png", Depth::Sixteen) <= 2. / 65535.);
+    }
+
+    #[test]
+    fn raw_round_trips_exactly() {
+        assert_eq!(round_trip("raw.r32", Depth::Eight), 0.);
+    }
+}
@@ -0,0 +1,7 @@
+pub mod chunks;
+pub mod coloring;
+pub mod erosion;
+pub mod fractal;
+pub mod heightfield;
+pub mod heightmap;
+pub mod water;
@@ -0,0 +1,215 @@
+use std::f32::consts::PI;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use nannou::color::STEELBLUE;
+use serde::Deserialize;
+
+use crate::model::figure::{Edge, Face, Obj3D, Vertex};
+use crate::model::mat::{cross, unit};
+use crate::model::material::Material;
+
+const GRAVITY: f32 = 9.81;
+
+/// Trochoidal wave moving over the x, z plane.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub struct Wave {
+    /// Way the crests travel, need not be unit length.
+    pub direction: [f32; 2],
+    /// Distance between crests.
+    pub wavelength: f32,
+    /// Height of the crests above the level.
+    pub amplitude: f32,
+    /// 0 gives a sine wave, 1 the sharpest crests a surface of waves this
+    /// steep keeps without looping over itself.
+    #[serde(default = "half")]
+    pub steepness: f32,
+    /// Distance the crests move per unit of time, None for the speed of
+    /// deep water waves of the wavelength.
+    #[serde(default)]
+    pub speed: Option<f32>,
+}
+
+impl Wave {
+    pub fn new(direction: [f32; 2], wavelength: f32, amplitude: f32) -> Wave {
+        Wave {
+            direction,
+            wavelength,
+            amplitude,
+            steepness: 0.5,
+            speed: None,
+        }
+    }
+
+    pub fn with_steepness(mut self, steepness: f32) -> Wave {
+        self.steepness = steepness;
+        self
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Wave {
+        self.speed = Some(speed);
+        self
+    }
+
+    /// Crests per unit of distance times 2 pi.
+    fn number(&self) -> f32 {
+        2. * PI / self.wavelength.max(1e-6)
+    }
+}
+
+/// Sum of Gerstner waves moving the points of a flat surface at `level` in
+/// circles, for example `{"level": -0.02, "waves": [{"direction": [1, 0.3],
+/// "wavelength": 0.8, "amplitude": 0.01}]}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Water {
+    #[serde(default)]
+    pub level: f32,
+    #[serde(default)]
+    pub waves: Vec<Wave>,
+    /// Linear color of the troughs.
+    #[serde(default = "deep")]
+    pub color: [f32; 3],
+    /// Linear color of the crests, showing where the surface is steep.
+    #[serde(default = "foam")]
+    pub foam: [f32; 3],
+}
+
+impl Water {
+    /// Still water.
+    pub fn new(level: f32) -> Water {
+        Water {
+            level,
+            waves: vec![],
+            color: deep(),
+            foam: foam(),
+        }
+    }
+
+    pub fn with_wave(mut self, wave: Wave) -> Water {
+        self.waves.push(wave);
+        self
+    }
+
+    pub fn with_colors(mut self, color: [f32; 3], foam: [f32; 3]) -> Water {
+        self.color = color;
+        self.foam = foam;
+        self
+    }
+
+    /// Where the point resting at x, z is at time `t`, and the unit normal
+    /// there from the derivatives of the sum.
+    pub fn sample(&self, x: f32, z: f32, t: f32) -> ([f32; 3], [f32; 3]) {
+        let mut p = [x, self.level, z];
+        // derivatives of the position along x and along z
+        let mut dx = [1., 0., 0.];
+        let mut dz = [0., 0., 1.];
+        let n = self.waves.len() as f32;
+        for wave in self.waves.iter() {
+            let [u, v] = wave.direction;
+            let len = u.hypot(v);
+            if len < 1e-6 {
+                continue;
+            }
+            let d = [u / len, v / len];
+            let k = wave.number();
+            let a = wave.amplitude;
+            let speed = wave.speed.unwrap_or_else(|| (GRAVITY / k).sqrt());
+            // sharpness shared among the waves so their sum stays a surface
+            let q = if a * k > 0. {
+                wave.steepness.clamp(0., 1.) / (a * k * n)
+            } else {
+                0.
+            };
+
+            let phase = k * (d[0] * x + d[1] * z - speed * t);
+            let (sin, cos) = phase.sin_cos();
+            p[0] += q * a * d[0] * cos;
+            p[1] += a * sin;
+            p[2] += q * a * d[1] * cos;
+
+            let (wa, qa) = (k * a, q * k * a);
+            dx[0] -= qa * d[0] * d[0] * sin;
+            dx[1] += wa * d[0] * cos;
+            dx[2] -= qa * d[0] * d[1] * sin;
+            dz[0] -= qa * d[0] * d[1] * sin;
+            dz[1] += wa * d[1] * cos;
+            dz[2] -= qa * d[1] * d[1] * sin;
+        }
+        (p, unit(cross(dz, dx)))
+    }
+
+    /// Grid of `resolution` points over `extent` from `origin` at time `t`,
+    /// placed relative to `origin`, like the faces and edges of
+    /// `Heightfield::to_obj`. Corners blend from the water color to foam
+    /// as the surface steepens.
+    pub fn to_obj(
+        &self,
+        resolution: [usize; 2],
+        extent: [f32; 2],
+        origin: [f32; 2],
+        t: f32,
+    ) -> Obj3D {
+        let [w, h] = resolution.map(|n| n.max(2));
+        let [sx, sz] = [extent[0] / (w - 1) as f32, extent[1] / (h - 1) as f32];
+        let index = |x: usize, z: usize| z * w + x;
+
+        let mut obj = Obj3D::new();
+        let mut colors = Vec::with_capacity(w * h);
+        for z in 0..h {
+            for x in 0..w {
+                let (x0, z0) = (origin[0] + x as f32 * sx, origin[1] + z as f32 * sz);
+                let (p, normal) = self.sample(x0, z0, t);
+                obj.push_vertex(Vertex::from_vec([p[0] - origin[0], p[1], p[2] - origin[1]]));
+                let crest = ((1. - normal[1]) * 8.).clamp(0., 1.);
+                colors.push(
+                    [0, 1, 2].map(|i| self.color[i] + (self.foam[i] - self.color[i]) * crest),
+                );
+            }
+        }
+
+        for z in 0..h - 1 {
+            for x in 0..w - 1 {
+                let (a, b, c, d) = ((x, z), (x, z + 1), (x + 1, z), (x + 1, z + 1));
+                for corners in [[a, b, c], [c, b, d]] {
+                    let vertexes = corners.map(|(x, z)| index(x, z));
+                    obj.push_face(Face::new(vertexes).with_colors(vertexes.map(|i| colors[i])));
+                }
+            }
+        }
+
+        for z in 0..h {
+            for x in 0..w {
+                if x < w - 1 {
+                    obj.push_edge(Edge::new_color(index(x, z), index(x + 1, z), STEELBLUE));
+                }
+                if z < h - 1 {
+                    obj.push_edge(Edge::new_color(index(x, z), index(x, z + 1), STEELBLUE));
+                }
+            }
+        }
+        obj.set_material(Material::lambertian([1., 1., 1.]));
+        obj
+    }
+}
+
+pub fn parse_water(src: &str) -> Result<Water, String> {
+    serde_json::from_str(src).map_err(|e| e.to_string())
+}
+
+pub fn load_water<P: AsRef<Path>>(path: P) -> io::Result<Water> {
+    let src = fs::read_to_string(path)?;
+    parse_water(&src).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn half() -> f32 {
+    0.5
+}
+
+fn deep() -> [f32; 3] {
+    [0.02, 0.09, 0.25]
+}
+
+fn foam() -> [f32; 3] {
+    [0.75, 0.8, 0.85]
+}
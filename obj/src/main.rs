@@ -1,18 +1,35 @@
 use std::rc::Rc;
+use std::sync::Arc;
 
+use common::model::aov::Aov;
+use common::model::environment::Environment;
 use common::model::figure::*;
+use common::model::graph::SceneGraph;
 use common::model::mat::*;
+use common::model::material::Material;
+use common::model::primitives::cuboid;
+use common::model::procedural::Procedural;
+use common::model::raster::Raster;
+use common::model::scene::load_scene;
+use common::model::texture::TextureMap;
 use nannou::color::*;
 use nannou::event::WindowEvent::*;
 use nannou::event::*;
 use nannou::*;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use wgpu::Texture;
 use winit::event::VirtualKeyCode::*;
 
+/// Direction of the light shading the rasterized faces.
+const LIGHT: [f32; 3] = [-1., -1., -1.];
+
 struct Model {
+    texture: Texture,
     eye: [f32; 3],
     at: [f32; 3],
     up: [f32; 3],
+    /// Vertical field of view in degrees.
+    fov: f32,
     width: f32,
     height: f32,
     camera: Mat4x4,
@@ -23,7 +40,15 @@ struct Model {
     mouse_y: f32,
     mouse_pressed: bool,
     alt: bool,
-    mesh: Mesh,
+    /// Objects of the scene, placed in world space by their nodes.
+    graph: SceneGraph,
+    /// Background of the rasterized view.
+    sky: Environment,
+    /// Projection times camera of this and of the previous frame.
+    view_proj: Mat4x4,
+    previous_view_proj: Mat4x4,
+    /// Set to write the AOVs of the next frame.
+    export_aovs: Cell<bool>,
 }
 
 fn main() {
@@ -32,7 +57,16 @@ fn main() {
 
 fn model(app: &App) -> Model {
     app.new_window().size(640, 480).event(event).view(view).build().unwrap();
-    let viewport = app.window_rect();
+
+    // `obj scene.json` shows a scene file instead of the demo cube
+    if let Some(path) = std::env::args().nth(1) {
+        let file = load_scene(&path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        });
+        let c = file.camera;
+        return new_model(app, (c.eye, c.at, c.up), c.fov, file.graph, file.environment);
+    }
 
     let eye = [0., 0.0, 0.0];
     let at = [0.45, 0.15, -1.3];
@@ -50,50 +84,53 @@ fn model(app: &App) -> Model {
     axis.push_edge(Edge::new_color(0, 2, RED).text("Y"));
     axis.push_edge(Edge::new_color(0, 3, RED).text("-Z"));
 
-    let cube = mesh.push_object("cube");
-    cube.push_vertex(Vertex::from([0.3, 0.3, -1.1, 1.]));
-    cube.push_vertex(Vertex::from([0.6, 0.3, -1.1, 1.]));
-    cube.push_vertex(Vertex::from([0.6, 0., -1.1, 1.]));
-    cube.push_vertex(Vertex::from([0.3, 0., -1.1, 1.]));
-
-    cube.push_vertex(Vertex::from([0.3, 0.3, -1.4, 1.]));
-    cube.push_vertex(Vertex::from([0.6, 0.3, -1.4, 1.]));
-    cube.push_vertex(Vertex::from([0.6, 0., -1.4, 1.]));
-    cube.push_vertex(Vertex::from([0.3, 0., -1.4, 1.]));
-
-    cube.push_face(Face::new([0, 3, 2]));
-    cube.push_face(Face::new([2, 1, 0]));
-
-    cube.push_face(Face::new([0, 1, 5]));
-    cube.push_face(Face::new([5, 4, 0]));
-
-    cube.push_face(Face::new([1, 2, 6]));
-    cube.push_face(Face::new([6, 5, 1]));
-
-    cube.push_face(Face::new([3, 7, 6]));
-    cube.push_face(Face::new([6, 2, 3]));
+    let mut cube = cuboid([0.3; 3], 1);
+    cube.transform(&translation_mat(0.45, 0.15, -1.25));
+    cube.set_material(
+        Material::pbr([0.9, 0.6, 0.2], 0.0, 0.4).with_albedo_map(TextureMap::Procedural(
+            Arc::new(Procedural::checker(4., [[1., 1., 1.], [0.3, 0.3, 0.3]])),
+        )),
+    );
+    mesh.objects.insert(String::from("cube"), cube);
 
-    cube.push_face(Face::new([0, 4, 7]));
-    cube.push_face(Face::new([7, 3, 0]));
+    new_model(app, (eye, at, up), 60., SceneGraph::from(mesh), Environment::sky())
+}
 
-    cube.push_face(Face::new([4, 5, 6]));
-    cube.push_face(Face::new([6, 7, 4]));
+fn new_model(
+    app: &App,
+    (eye, at, up): ([f32; 3], [f32; 3], [f32; 3]),
+    fov: f32,
+    graph: SceneGraph,
+    sky: Environment,
+) -> Model {
+    let viewport = app.window_rect();
+    let texture = wgpu::TextureBuilder::new()
+        .size([viewport.w() as u32, viewport.h() as u32])
+        .format(wgpu::TextureFormat::Rgba8Unorm)
+        .usage(wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING)
+        .build(app.main_window().device());
 
     Model {
+        texture,
         eye,
         at,
         up,
+        fov,
         width: viewport.w(),
         height: viewport.h(),
         camera: viewer(eye, at, up),
-        perspective_proj: perspective_projection(60., viewport.w() / viewport.h(), -10., -1.),
+        perspective_proj: perspective_projection(fov, viewport.w() / viewport.h(), -10., -1.),
         mouse_x_pressed: 0.0,
         mouse_y_pressed: 0.0,
         mouse_x: 0.0,
         mouse_y: 0.0,
         mouse_pressed: false,
         alt: false,
-        mesh,
+        graph,
+        sky,
+        view_proj: Mat4x4::unit(),
+        previous_view_proj: Mat4x4::unit(),
+        export_aovs: Cell::new(false),
     }
 }
 
@@ -127,6 +164,7 @@ fn event(_app: &App, model: &mut Model, event: WindowEvent) {
                     model.camera = viewer(model.eye, model.at, model.up);
                 }
             }
+            A => model.export_aovs.set(true),
             LAlt => model.alt = true,
             _ => {}
         },
@@ -164,14 +202,17 @@ fn event(_app: &App, model: &mut Model, event: WindowEvent) {
         }
 
         Resized(dim) => {
-            model.perspective_proj = perspective_projection(60., dim[0] / dim[1], -100., -1.)
+            model.perspective_proj = perspective_projection(model.fov, dim[0] / dim[1], -100., -1.)
         }
 
         _ => {}
     }
 }
 
-fn update(_app: &App, _model: &mut Model, _update: Update) {}
+fn update(_app: &App, model: &mut Model, _update: Update) {
+    model.previous_view_proj = model.view_proj;
+    model.view_proj = model.perspective_proj * model.camera;
+}
 
 
 fn view(app: &App, model: &Model, frame: Frame) {
@@ -182,19 +223,48 @@ fn view(app: &App, model: &Model, frame: Frame) {
 
     let viewport = app.window_rect();
 
-    let transform = Mat4x4::unit();
+    let [w, h] = model.texture.size();
+    let export = model.export_aovs.replace(false);
+    let mut raster = Raster::new(w as usize, h as usize);
+    if export {
+        raster = raster.with_aovs(&Aov::ALL);
+    }
+    // shared objects are projected per placement, not copied
+    let view_proj = model.perspective_proj * model.camera;
+    let instances = model.graph.instances();
+    for (id, obj) in model.graph.objects().iter().enumerate() {
+        raster.draw_instances(
+            obj,
+            &instances[id],
+            view_proj,
+            model.previous_view_proj,
+            LIGHT,
+            id,
+        );
+    }
+    raster.draw_environment(&model.sky, model.eye, model.at, model.up, model.fov);
 
-    let mut new_mesh = model.mesh.clone();
+    if export {
+        if let Err(e) = raster.aovs.save_images("raster.png") {
+            println!("Could not write the AOV images: {}", e);
+        }
+        let beauty: Vec<[f32; 3]> = raster.color.iter().map(|c| [c[0], c[1], c[2]]).collect();
+        if let Err(e) = raster.aovs.save_exr("raster.exr", Some(&beauty)) {
+            println!("Could not write raster.exr: {}", e);
+        }
+    }
 
-    new_mesh.set_camera(model.eye);
-    
-    let mat = (model.perspective_proj * model.camera * transform * &new_mesh).to_screen(
-        viewport.w() as f32,
-        viewport.h() as f32,
+    let image = raster.to_image();
+    model.texture.upload_data(
+        app.main_window().device(),
+        &mut frame.command_encoder(),
+        image.as_flat_samples().as_slice(),
     );
+    draw.texture(&model.texture);
 
-    mat.draw_faces(&draw);
-    mat.draw_lines(&draw);
+    for (id, obj) in model.graph.objects().iter().enumerate() {
+        obj.draw_instance_lines(&draw, &instances[id], view_proj, viewport.w(), viewport.h());
+    }
 
     draw.to_frame(app, &frame).unwrap();
 }
@@ -0,0 +1,192 @@
+use std::f32::consts::PI;
+
+use common::model::mat::*;
+use common::model::material::{Material, MaterialKind};
+use common::model::rng::Rng;
+
+use crate::integrator::cosine_hemisphere;
+
+/// Outcome of importance sampling a material.
+#[derive(Debug, Copy, Clone)]
+pub struct BsdfSample {
+    pub wi: [f32; 3],
+    /// `f * cos / pdf`, the factor the path throughput is multiplied by.
+    pub weight: [f32; 3],
+    /// Solid angle density of `wi`, None for delta lobes.
+    pub pdf: Option<f32>,
+}
+
+/// Reflected radiance factor `f(wo, wi) * cos(wi)` for a non delta material.
+/// `n` is the shading normal on the side of `wo`.
+pub fn eval(m: &Material, n: [f32; 3], wo: [f32; 3], wi: [f32; 3]) -> [f32; 3] {
+    let cos_i = dot(n, wi);
+    let cos_o = dot(n, wo);
+    if cos_i <= 0.0 || cos_o <= 0.0 || m.kind == MaterialKind::Dielectric {
+        return [0., 0., 0.];
+    }
+
+    let (diffuse, specular) = lobes(m);
+    let mut f = mul(diffuse, 1. / PI);
+
+    if specular_weight(m) > 0.0 {
+        let h = unit(add(wo, wi));
+        let a2 = alpha(m) * alpha(m);
+        let d = ggx_d(dot(n, h), a2);
+        let g = smith_g1(cos_o, a2) * smith_g1(cos_i, a2);
+        let fr = schlick(specular, dot(wo, h));
+        f = add(f, mul(fr, d * g / (4. * cos_o * cos_i)));
+    }
+
+    mul(f, cos_i)
+}
+
+pub fn pdf(m: &Material, n: [f32; 3], wo: [f32; 3], wi: [f32; 3]) -> f32 {
+    let cos_i = dot(n, wi);
+    if cos_i <= 0.0 || m.kind == MaterialKind::Dielectric {
+        return 0.0;
+    }
+
+    let p_spec = specular_weight(m);
+    let mut pdf = (1. - p_spec) * cos_i / PI;
+
+    if p_spec > 0.0 {
+        let h = unit(add(wo, wi));
+        let a2 = alpha(m) * alpha(m);
+        let cos_h = dot(n, h);
+        pdf += p_spec * ggx_d(cos_h, a2) * cos_h / (4. * dot(wo, h)).max(1e-6);
+    }
+
+    pdf
+}
+
+pub fn sample(
+    m: &Material,
+    n: [f32; 3],
+    wo: [f32; 3],
+    front_face: bool,
+    rng: &mut Rng,
+) -> Option<BsdfSample> {
+    if m.kind == MaterialKind::Dielectric {
+        return Some(sample_dielectric(m, n, wo, front_face, rng));
+    }
+
+    let u1 = rng.next_f32();
+    let u2 = rng.next_f32();
+
+    let wi = if rng.next_f32() < specular_weight(m) {
+        let h = ggx_sample(n, alpha(m) * alpha(m), u1, u2);
+        reflect(neg(wo), h)
+    } else {
+        cosine_hemisphere(n, u1, u2)
+    };
+
+    let pdf = pdf(m, n, wo, wi);
+    if pdf <= 0.0 {
+        return None;
+    }
+
+    Some(BsdfSample {
+        wi,
+        weight: mul(eval(m, n, wo, wi), 1. / pdf),
+        pdf: Some(pdf),
+    })
+}
+
+pub fn reflect(d: [f32; 3], n: [f32; 3]) -> [f32; 3] {
+    diff(d, mul(n, 2. * dot(d, n)))
+}
+
+fn sample_dielectric(
+    m: &Material,
+    n: [f32; 3],
+    wo: [f32; 3],
+    front_face: bool,
+    rng: &mut Rng,
+) -> BsdfSample {
+    let eta = if front_face { 1. / m.ior } else { m.ior };
+    let cos_o = dot(n, wo).min(1.);
+    let sin2_t = eta * eta * (1. - cos_o * cos_o);
+
+    let reflectance = if sin2_t >= 1. {
+        1.
+    } else {
+        fresnel_dielectric(cos_o, (1. - sin2_t).sqrt(), eta)
+    };
+
+    let wi = if rng.next_f32() < reflectance {
+        reflect(neg(wo), n)
+    } else {
+        let cos_t = (1. - sin2_t).sqrt();
+        unit(add(mul(neg(wo), eta), mul(n, eta * cos_o - cos_t)))
+    };
+
+    BsdfSample {
+        wi,
+        weight: m.albedo,
+        pdf: None,
+    }
+}
+
+/// Diffuse albedo and specular reflectance at normal incidence.
+fn lobes(m: &Material) -> ([f32; 3], [f32; 3]) {
+    match m.kind {
+        MaterialKind::Lambertian => (m.albedo, [0., 0., 0.]),
+        MaterialKind::Metal => ([0., 0., 0.], m.albedo),
+        MaterialKind::Pbr => {
+            let f0 = add(
+                mul([0.04, 0.04, 0.04], 1. - m.metallic),
+                mul(m.albedo, m.metallic),
+            );
+            (mul(m.albedo, 1. - m.metallic), f0)
+        }
+        MaterialKind::Dielectric | MaterialKind::Emissive => ([0., 0., 0.], [0., 0., 0.]),
+    }
+}
+
+/// Probability of sampling the specular lobe.
+fn specular_weight(m: &Material) -> f32 {
+    match m.kind {
+        MaterialKind::Metal => 1.,
+        MaterialKind::Pbr => 0.5 + 0.5 * m.metallic,
+        _ => 0.,
+    }
+}
+
+fn alpha(m: &Material) -> f32 {
+    (m.roughness * m.roughness).max(1e-3)
+}
+
+fn ggx_d(cos_h: f32, a2: f32) -> f32 {
+    if cos_h <= 0.0 {
+        return 0.0;
+    }
+    let k = cos_h * cos_h * (a2 - 1.) + 1.;
+    a2 / (PI * k * k)
+}
+
+fn smith_g1(cos: f32, a2: f32) -> f32 {
+    2. * cos / (cos + (a2 + (1. - a2) * cos * cos).sqrt())
+}
+
+fn ggx_sample(n: [f32; 3], a2: f32, u1: f32, u2: f32) -> [f32; 3] {
+    let cos_t = ((1. - u1) / (1. + (a2 - 1.) * u1)).sqrt();
+    let sin_t = (1. - cos_t * cos_t).max(0.).sqrt();
+    let phi = 2. * PI * u2;
+    let (t, b) = basis(n);
+
+    unit(add(
+        add(mul(t, sin_t * phi.cos()), mul(b, sin_t * phi.sin())),
+        mul(n, cos_t),
+    ))
+}
+
+fn schlick(f0: [f32; 3], cos: f32) -> [f32; 3] {
+    let k = (1. - cos.clamp(0., 1.)).powi(5);
+    add(f0, mul(diff([1., 1., 1.], f0), k))
+}
+
+fn fresnel_dielectric(cos_i: f32, cos_t: f32, eta: f32) -> f32 {
+    let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
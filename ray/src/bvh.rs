@@ -0,0 +1,211 @@
+use common::model::mat::*;
+
+use crate::tracer::Ray;
+
+/// Primitives kept together in a leaf.
+const LEAF_SIZE: usize = 4;
+
+/// Axis aligned bounding box.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Aabb {
+    /// Box containing nothing, the identity of `union`.
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: [f32::INFINITY; 3],
+            max: [f32::NEG_INFINITY; 3],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|i| self.min[i] > self.max[i])
+    }
+
+    pub fn grow(self, p: [f32; 3]) -> Aabb {
+        Aabb {
+            min: [0, 1, 2].map(|i| self.min[i].min(p[i])),
+            max: [0, 1, 2].map(|i| self.max[i].max(p[i])),
+        }
+    }
+
+    pub fn union(self, other: Aabb) -> Aabb {
+        self.grow(other.min).grow(other.max)
+    }
+
+    pub fn center(&self) -> [f32; 3] {
+        mul(add(self.min, self.max), 0.5)
+    }
+
+    /// Box around the transformed corners.
+    pub fn transform(&self, m: &Mat4x4) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        (0..8).fold(Aabb::empty(), |b, k| {
+            let corner = [0, 1, 2].map(|i| {
+                if k & (1 << i) == 0 {
+                    self.min[i]
+                } else {
+                    self.max[i]
+                }
+            });
+            b.grow(m.mul_point(corner))
+        })
+    }
+
+    /// Distance the ray enters the box at, if before `t_max`. `inv_dir` is
+    /// the component wise inverse of the ray direction.
+    fn hit(&self, origin: [f32; 3], inv_dir: [f32; 3], t_max: f32) -> Option<f32> {
+        let mut t0: f32 = 0.;
+        let mut t1 = t_max;
+        for i in 0..3 {
+            let a = (self.min[i] - origin[i]) * inv_dir[i];
+            let b = (self.max[i] - origin[i]) * inv_dir[i];
+            t0 = t0.max(a.min(b));
+            t1 = t1.min(a.max(b));
+        }
+        (t0 <= t1).then_some(t0)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Node {
+    bounds: Aabb,
+    /// Leaves hold `count` items from `start`, inner nodes have `count` 0,
+    /// their first child right after them and the second at `start`.
+    start: usize,
+    count: usize,
+}
+
+/// Bounding volume hierarchy over the boxes of some primitives, which are
+/// referred to by their index in the slice it was built from.
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    items: Vec<usize>,
+}
+
+impl Bvh {
+    /// Splits the boxes at the median of their centers along the widest axis.
+    pub fn build(boxes: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(boxes.len() * 2),
+            items: (0..boxes.len()).collect(),
+        };
+        if !boxes.is_empty() {
+            bvh.split(boxes, 0, boxes.len());
+        }
+        bvh
+    }
+
+    /// Bounds of every primitive.
+    pub fn bounds(&self) -> Aabb {
+        self.nodes
+            .first()
+            .map(|n| n.bounds)
+            .unwrap_or(Aabb::empty())
+    }
+
+    fn split(&mut self, boxes: &[Aabb], start: usize, end: usize) -> usize {
+        let items = &mut self.items[start..end];
+        let bounds = items.iter().fold(Aabb::empty(), |b, i| b.union(boxes[*i]));
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            bounds,
+            start,
+            count: end - start,
+        });
+        if end - start <= LEAF_SIZE {
+            return index;
+        }
+
+        let centers = items
+            .iter()
+            .fold(Aabb::empty(), |b, i| b.grow(boxes[*i].center()));
+        let extent = diff(centers.max, centers.min);
+        let axis = if extent[0] > extent[1].max(extent[2]) {
+            0
+        } else if extent[1] > extent[2] {
+            1
+        } else {
+            2
+        };
+
+        let mid = items.len() / 2;
+        items.select_nth_unstable_by(mid, |a, b| {
+            boxes[*a].center()[axis].total_cmp(&boxes[*b].center()[axis])
+        });
+
+        self.split(boxes, start, start + mid);
+        let second = self.split(boxes, start + mid, end);
+        self.nodes[index].start = second;
+        self.nodes[index].count = 0;
+        index
+    }
+
+    /// Calls `visit` with the items whose boxes the ray enters before the
+    /// current `t_max`, nearer boxes first. `visit` returns the distance of a
+    /// hit, which then becomes `t_max`.
+    pub fn traverse(
+        &self,
+        ray: &Ray,
+        mut t_max: f32,
+        mut visit: impl FnMut(usize, f32) -> Option<f32>,
+    ) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let inv_dir = ray.dir.map(|d| 1. / d);
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.bounds.hit(ray.origin, inv_dir, t_max).is_none() {
+                continue;
+            }
+
+            if node.count > 0 {
+                for item in self.items[node.start..node.start + node.count].iter() {
+                    if let Some(t) = visit(*item, t_max) {
+                        t_max = t_max.min(t);
+                    }
+                }
+                continue;
+            }
+
+            let (first, second) = (index + 1, node.start);
+            let near = |i: usize| {
+                self.nodes[i]
+                    .bounds
+                    .hit(ray.origin, inv_dir, t_max)
+                    .unwrap_or(f32::INFINITY)
+            };
+            // the nearer child is popped first
+            if near(first) <= near(second) {
+                stack.push(second);
+                stack.push(first);
+            } else {
+                stack.push(first);
+                stack.push(second);
+            }
+        }
+    }
+
+    /// True when `visit` reports a hit for any item the ray may reach.
+    pub fn any(&self, ray: &Ray, t_max: f32, mut visit: impl FnMut(usize) -> bool) -> bool {
+        let mut found = false;
+        self.traverse(ray, t_max, |item, _| {
+            if found {
+                return None;
+            }
+            found = visit(item);
+            // a zero distance stops the traversal
+            found.then_some(0.)
+        });
+        found
+    }
+}
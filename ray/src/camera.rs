@@ -0,0 +1,99 @@
+use common::model::mat::*;
+
+use crate::tracer::Ray;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Camera {
+    pub eye: [f32; 3],
+    u: [f32; 3],
+    v: [f32; 3],
+    n: [f32; 3],
+    scale: f32,
+    /// Lens radius, 0 for a pinhole camera with everything in focus.
+    pub aperture: f32,
+    /// Distance along the view direction of the plane in focus.
+    pub focus_distance: f32,
+    /// Open and close times of the shutter, in the time of object motions.
+    pub shutter: (f32, f32),
+}
+
+impl Camera {
+    pub fn new(eye: [f32; 3], at: [f32; 3], up: [f32; 3], fov: f32) -> Camera {
+        let n = unit(diff(eye, at));
+        let u = unit(cross(up, n));
+        let v = cross(n, u);
+
+        Camera {
+            eye,
+            u,
+            v,
+            n,
+            scale: (fov * 0.5 * std::f32::consts::PI / 180.).tan(),
+            aperture: 0.,
+            focus_distance: dot(diff(at, eye), diff(at, eye)).sqrt(),
+            shutter: (0., 0.),
+        }
+    }
+
+    /// Primary ray through a screen point, `x` in [-aspect, aspect] and `y` in [-1, 1].
+    pub fn ray(&self, x: f32, y: f32) -> Ray {
+        let dir = add(
+            add(mul(self.u, x * self.scale), mul(self.v, y * self.scale)),
+            neg(self.n),
+        );
+        Ray::new(self.eye, unit(dir))
+    }
+
+    /// Primary ray through a screen point leaving from a point of the lens,
+    /// `lens` and `time` in [0, 1) picking the lens point and shutter instant.
+    pub fn sample_ray(&self, x: f32, y: f32, lens: [f32; 2], time: f32) -> Ray {
+        let pinhole = self.ray(x, y);
+        let (open, close) = self.shutter;
+        let time = open + (close - open) * time;
+
+        if self.aperture <= 0. {
+            return Ray { time, ..pinhole };
+        }
+
+        // every ray through the same screen point meets on the focus plane
+        let focus = pinhole.at(self.focus_distance / -dot(pinhole.dir, self.n));
+        let r = self.aperture * lens[0].sqrt();
+        let phi = 2. * std::f32::consts::PI * lens[1];
+        let origin = add(
+            self.eye,
+            add(mul(self.u, r * phi.cos()), mul(self.v, r * phi.sin())),
+        );
+
+        Ray {
+            time,
+            ..Ray::new(origin, unit(diff(focus, origin)))
+        }
+    }
+
+    /// Pixel position of a world point in a `width` x `height` image, and its
+    /// depth along the view direction. None behind the eye.
+    pub fn project(&self, p: [f32; 3], (width, height): (usize, usize)) -> Option<([f32; 2], f32)> {
+        let d = diff(p, self.eye);
+        let depth = -dot(d, self.n);
+        if depth <= 0. {
+            return None;
+        }
+
+        let ratio = width as f32 / height as f32;
+        let x = dot(d, self.u) / (depth * self.scale * ratio);
+        let y = dot(d, self.v) / (depth * self.scale);
+        Some((
+            [
+                (x + 1.) * 0.5 * width as f32,
+                (1. - y) * 0.5 * height as f32,
+            ],
+            depth,
+        ))
+    }
+
+    /// Angle between the primary rays of neighbouring pixels, for an image
+    /// `height` pixels tall.
+    pub fn pixel_spread(&self, height: usize) -> f32 {
+        2. * self.scale / height as f32
+    }
+}
@@ -0,0 +1,127 @@
+use common::model::mat::*;
+use rayon::prelude::*;
+
+use crate::film::{Features, Film};
+
+/// Taps of the B3 spline the wavelet passes are built from.
+const KERNEL: [f32; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+/// Edge avoiding à-trous wavelet filter. Every pass blurs with a 5x5 kernel
+/// whose taps are spread twice as far apart as in the pass before, and
+/// drops the taps whose color or features differ too much from the center.
+#[derive(Debug, Copy, Clone)]
+pub struct Denoiser {
+    pub iterations: u32,
+    /// Color tolerance of the first pass, halved at each following pass.
+    pub sigma_color: f32,
+    pub sigma_normal: f32,
+    pub sigma_albedo: f32,
+    /// Depth tolerance relative to the depth of the center pixel.
+    pub sigma_depth: f32,
+}
+
+impl Denoiser {
+    pub fn new() -> Denoiser {
+        Denoiser {
+            iterations: 5,
+            sigma_color: 0.5,
+            sigma_normal: 0.3,
+            sigma_albedo: 0.1,
+            sigma_depth: 0.05,
+        }
+    }
+
+    /// Denoised copy of the film. The lighting is filtered on its own and the
+    /// albedo multiplied back, so textures stay sharp.
+    pub fn apply(&self, film: &Film) -> Film {
+        let (width, height) = (film.width, film.height);
+        let features: Vec<Features> = (0..width * height)
+            .map(|i| film.features(i % width, i / width))
+            .collect();
+        let albedo: Vec<[f32; 3]> = features
+            .iter()
+            .map(|f| f.albedo.map(|a| if a > 0.01 { a } else { 1. }))
+            .collect();
+
+        let mut light: Vec<[f32; 3]> = (0..width * height)
+            .map(|i| {
+                let c = film.pixel(i % width, i / width);
+                [0, 1, 2].map(|k| c[k] / albedo[i][k])
+            })
+            .collect();
+
+        for pass in 0..self.iterations {
+            let step = 1 << pass;
+            let sigma_color = self.sigma_color / step as f32;
+            light = (0..width * height)
+                .into_par_iter()
+                .map(|i| self.filter(&light, &features, (width, height), i, step, sigma_color))
+                .collect();
+        }
+
+        film.with_colors(
+            light
+                .iter()
+                .zip(albedo.iter())
+                .map(|(l, a)| comp_dot(*l, *a))
+                .collect(),
+        )
+    }
+
+    fn filter(
+        &self,
+        light: &[[f32; 3]],
+        features: &[Features],
+        (width, height): (usize, usize),
+        i: usize,
+        step: usize,
+        sigma_color: f32,
+    ) -> [f32; 3] {
+        let (x, y) = ((i % width) as isize, (i / width) as isize);
+        let center = features[i];
+        let c = compress(light[i]);
+
+        let mut sum = [0., 0., 0.];
+        let mut total = 0.;
+        for (ky, hy) in KERNEL.iter().enumerate() {
+            for (kx, hx) in KERNEL.iter().enumerate() {
+                let qx = x + (kx as isize - 2) * step as isize;
+                let qy = y + (ky as isize - 2) * step as isize;
+                if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                    continue;
+                }
+
+                let j = qy as usize * width + qx as usize;
+                let f = features[j];
+                let dc = diff(compress(light[j]), c);
+                let dn = diff(f.normal, center.normal);
+                let da = diff(f.albedo, center.albedo);
+                let dz = (f.depth - center.depth).abs() / center.depth.max(1e-3);
+
+                let w = hx
+                    * hy
+                    * (-dot(dc, dc) / (sigma_color * sigma_color)).exp()
+                    * (-dot(dn, dn) / (self.sigma_normal * self.sigma_normal)).exp()
+                    * (-dot(da, da) / (self.sigma_albedo * self.sigma_albedo)).exp()
+                    * (-dz / self.sigma_depth).exp();
+
+                sum = add(sum, mul(light[j], w));
+                total += w;
+            }
+        }
+
+        // the center tap always has a positive weight
+        mul(sum, 1. / total)
+    }
+}
+
+impl Default for Denoiser {
+    fn default() -> Denoiser {
+        Denoiser::new()
+    }
+}
+
+/// Bounded copy of a radiance value, for comparing colors.
+fn compress(c: [f32; 3]) -> [f32; 3] {
+    c.map(|v| v.max(0.) / (1. + v.max(0.)))
+}
@@ -0,0 +1,225 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use common::model::mat::{add, mul};
+use common::model::texture::srgb_encode;
+use nannou::image::codecs::hdr::HdrEncoder;
+use nannou::image::{ImageResult, Rgb, Rgba, RgbaImage};
+
+use crate::render::TileResult;
+
+/// First surface seen through a pixel, averaged over its samples. Guides
+/// the denoiser. Pixels seeing the environment have zero normal and depth.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Features {
+    pub normal: [f32; 3],
+    pub albedo: [f32; 3],
+    pub depth: f32,
+}
+
+impl Features {
+    pub fn add(self, other: Features) -> Features {
+        Features {
+            normal: add(self.normal, other.normal),
+            albedo: add(self.albedo, other.albedo),
+            depth: self.depth + other.depth,
+        }
+    }
+
+    pub fn scale(self, s: f32) -> Features {
+        Features {
+            normal: mul(self.normal, s),
+            albedo: mul(self.albedo, s),
+            depth: self.depth * s,
+        }
+    }
+}
+
+/// Accumulation buffer. Every pass adds filter weighted samples to each
+/// pixel and the displayed value is their weighted average.
+#[derive(Debug, Clone)]
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    pub passes: u32,
+    sum: Vec<[f32; 3]>,
+    weight: Vec<f32>,
+    /// Feature sums and the number of samples they add up.
+    features: Vec<Features>,
+    samples: Vec<u32>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Film {
+        Film {
+            width,
+            height,
+            passes: 0,
+            sum: vec![[0., 0., 0.]; width * height],
+            weight: vec![0.; width * height],
+            features: vec![Features::default(); width * height],
+            samples: vec![0; width * height],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.passes = 0;
+        self.sum.iter_mut().for_each(|c| *c = [0., 0., 0.]);
+        self.weight.iter_mut().for_each(|w| *w = 0.);
+        self.features.fill(Features::default());
+        self.samples.fill(0);
+    }
+
+    /// Adds the samples of a finished tile.
+    pub fn merge(&mut self, tile: &TileResult) {
+        let t = tile.tile;
+        for y in t.y0..t.y1 {
+            for x in t.x0..t.x1 {
+                let src = (y - t.y0) * (t.x1 - t.x0) + (x - t.x0);
+                let dst = y * self.width + x;
+                let s = &mut self.sum[dst];
+                s[0] += tile.sum[src][0];
+                s[1] += tile.sum[src][1];
+                s[2] += tile.sum[src][2];
+                self.weight[dst] += tile.weight[src];
+                self.features[dst] = self.features[dst].add(tile.features[src]);
+                self.samples[dst] += tile.samples;
+            }
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [f32; 4] {
+        let i = y * self.width + x;
+        let s = self.sum[i];
+        let w = self.weight[i];
+        if w.abs() < 1e-6 {
+            return [0., 0., 0., 1.];
+        }
+        [s[0] / w, s[1] / w, s[2] / w, 1.]
+    }
+
+    /// Linear colors, row major.
+    pub fn colors(&self) -> Vec<[f32; 3]> {
+        (0..self.width * self.height)
+            .map(|i| {
+                let c = self.pixel(i % self.width, i / self.width);
+                [c[0], c[1], c[2]]
+            })
+            .collect()
+    }
+
+    pub fn features(&self, x: usize, y: usize) -> Features {
+        let i = y * self.width + x;
+        match self.samples[i] {
+            0 => Features::default(),
+            n => self.features[i].scale(1. / n as f32),
+        }
+    }
+
+    /// Film with the same features showing `colors`, row major.
+    pub fn with_colors(&self, colors: Vec<[f32; 3]>) -> Film {
+        assert_eq!(colors.len(), self.width * self.height);
+        Film {
+            weight: vec![1.; colors.len()],
+            sum: colors,
+            ..self.clone()
+        }
+    }
+}
+
+impl Film {
+    /// Tone mapped 8 bit sRGB copy of the film.
+    pub fn to_image(&self, display: &Display) -> RgbaImage {
+        RgbaImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            display.to_rgba(self.pixel(x as usize, y as usize))
+        })
+    }
+
+    /// Writes linear radiance to a Radiance `.hdr` file, any other extension
+    /// gets the tone mapped image in the format the extension names.
+    pub fn save<P: AsRef<Path>>(&self, path: P, display: &Display) -> ImageResult<()> {
+        let path = path.as_ref();
+        let hdr = path
+            .extension()
+            .map(|e| e.eq_ignore_ascii_case("hdr"))
+            .unwrap_or(false);
+
+        if !hdr {
+            return self.to_image(display).save(path);
+        }
+
+        let mut data = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let c = self.pixel(x, y);
+                data.push(Rgb([c[0].max(0.), c[1].max(0.), c[2].max(0.)]));
+            }
+        }
+
+        let file = BufWriter::new(File::create(path)?);
+        HdrEncoder::new(file).encode(&data, self.width, self.height)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneMap {
+    Clamp,
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve.
+    Aces,
+}
+
+impl ToneMap {
+    pub fn next(self) -> ToneMap {
+        match self {
+            ToneMap::Clamp => ToneMap::Reinhard,
+            ToneMap::Reinhard => ToneMap::Aces,
+            ToneMap::Aces => ToneMap::Clamp,
+        }
+    }
+
+    pub fn apply(&self, c: [f32; 3]) -> [f32; 3] {
+        c.map(|v| {
+            let v = v.max(0.);
+            match self {
+                ToneMap::Clamp => v,
+                ToneMap::Reinhard => v / (1. + v),
+                ToneMap::Aces => (v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14),
+            }
+        })
+    }
+}
+
+/// Transform from scene radiance to display values.
+#[derive(Debug, Copy, Clone)]
+pub struct Display {
+    /// Exposure adjustment in stops.
+    pub exposure: f32,
+    pub tone_map: ToneMap,
+}
+
+impl Display {
+    pub fn new() -> Display {
+        Display {
+            exposure: 0.,
+            tone_map: ToneMap::Aces,
+        }
+    }
+
+    pub fn to_rgba(self, c: [f32; 4]) -> Rgba<u8> {
+        let scale = self.exposure.exp2();
+        let mapped = self
+            .tone_map
+            .apply([c[0] * scale, c[1] * scale, c[2] * scale]);
+        let [r, g, b] = mapped.map(|v| (srgb_encode(v) * 255. + 0.5) as u8);
+
+        Rgba([r, g, b, (c[3].clamp(0., 1.) * 255. + 0.5) as u8])
+    }
+}
+
+impl Default for Display {
+    fn default() -> Display {
+        Display::new()
+    }
+}
@@ -0,0 +1,378 @@
+use std::f32::consts::PI;
+
+use common::model::light::LightSample;
+use common::model::mat::*;
+use common::model::material::Material;
+use common::model::rng::Rng;
+
+use crate::bsdf;
+use crate::film::Features;
+use crate::medium;
+use crate::tracer::{Hit, Ray, Scene};
+
+#[derive(Debug, Copy, Clone)]
+pub struct PathTracer {
+    pub max_depth: u32,
+    /// Bounce after which paths are terminated with Russian roulette.
+    pub rr_depth: u32,
+}
+
+impl PathTracer {
+    pub fn new() -> PathTracer {
+        PathTracer {
+            max_depth: 8,
+            rr_depth: 3,
+        }
+    }
+
+    pub fn radiance(&self, scene: &Scene, ray: Ray, rng: &mut Rng) -> [f32; 3] {
+        let mut radiance = [0., 0., 0.];
+        let mut throughput = [1., 1., 1.];
+        let mut ray = ray;
+        // width of the primary ray cone, used to filter textures
+        let spread = ray.spread;
+        let mut width = 0.;
+        // density the last bounce chose `ray` with, None for camera rays and
+        // specular bounces
+        let mut bsdf_pdf: Option<f32> = None;
+
+        for depth in 0..self.max_depth {
+            let hit = scene.intersect(&ray, f32::INFINITY);
+            let t_max = hit.as_ref().map(|h| h.t).unwrap_or(f32::INFINITY);
+            // area lights are not geometry, paths reaching one end there
+            let light_hit = scene.intersect_light(&ray, t_max);
+            let t_max = light_hit.map(|(_, t)| t).unwrap_or(t_max);
+
+            let event = if scene.media.is_empty() {
+                None
+            } else {
+                let (event, weight) = medium::sample_interaction(&scene.media, &ray, t_max, rng);
+                throughput = comp_dot(throughput, weight);
+                event
+            };
+
+            if let Some(fog) = scene.fog.as_ref() {
+                let tr = fog.transmittance(&ray, event.map(|e| e.t).unwrap_or(t_max));
+                radiance = add(radiance, mul(comp_dot(throughput, fog.color), 1. - tr));
+                throughput = mul(throughput, tr);
+            }
+            if throughput == [0., 0., 0.] {
+                break;
+            }
+
+            if let Some(event) = event {
+                let point = ray.at(event.t);
+                let g = scene.media[event.medium].g;
+                let vertex = Vertex::Medium {
+                    point,
+                    g,
+                    time: ray.time,
+                };
+                let wo = neg(ray.dir);
+                radiance = add(
+                    radiance,
+                    comp_dot(throughput, direct(scene, &vertex, wo, rng)),
+                );
+
+                // phase sampling is exact, the throughput is unchanged
+                let (wi, pdf) = medium::sample_phase(g, ray.dir, [rng.next_f32(), rng.next_f32()]);
+                bsdf_pdf = Some(pdf);
+                throughput = match self.roulette(depth, throughput, rng) {
+                    Some(t) => t,
+                    None => break,
+                };
+                ray = vertex.spawn(wi);
+                continue;
+            }
+
+            if let Some((i, _)) = light_hit {
+                let light = &scene.lights[i];
+                let w = bsdf_pdf
+                    .map(|p| power_heuristic(p, light.pdf(ray.origin, ray.dir)))
+                    .unwrap_or(1.);
+                radiance = add(
+                    radiance,
+                    mul(comp_dot(throughput, light.emitted(ray.dir)), w),
+                );
+                break;
+            }
+
+            let hit = match hit {
+                Some(hit) => hit,
+                None => {
+                    let env = &scene.environment;
+                    if !env.is_black() {
+                        let w = bsdf_pdf
+                            .map(|p| power_heuristic(p, env.pdf(ray.dir)))
+                            .unwrap_or(1.);
+                        let le = env.radiance(ray.dir);
+                        radiance = add(radiance, mul(comp_dot(throughput, le), w));
+                    }
+                    break;
+                }
+            };
+
+            width += hit.t * spread;
+            let textured;
+            let mut material = &scene.materials[hit.material];
+            if material.albedo_map.is_some() || hit.tint != [1., 1., 1.] {
+                textured = Material {
+                    albedo: scene.albedo(&hit, width * hit.uv_density),
+                    ..material.clone()
+                };
+                material = &textured;
+            }
+            let wo = neg(ray.dir);
+
+            if hit.front_face && material.is_emissive() {
+                let w = bsdf_pdf
+                    .map(|p| power_heuristic(p, scene.emitter_pdf(&hit, ray.dir)))
+                    .unwrap_or(1.);
+                radiance = add(radiance, mul(comp_dot(throughput, material.emission), w));
+            }
+
+            let vertex = Vertex::Surface {
+                hit: &hit,
+                material,
+            };
+            radiance = add(
+                radiance,
+                comp_dot(throughput, direct(scene, &vertex, wo, rng)),
+            );
+
+            let sample = match bsdf::sample(material, hit.normal, wo, hit.front_face, rng) {
+                Some(sample) => sample,
+                None => break,
+            };
+            bsdf_pdf = sample.pdf;
+            throughput = comp_dot(throughput, sample.weight);
+            if throughput == [0., 0., 0.] {
+                break;
+            }
+
+            throughput = match self.roulette(depth, throughput, rng) {
+                Some(t) => t,
+                None => break,
+            };
+
+            ray = hit.spawn(sample.wi);
+        }
+
+        radiance
+    }
+}
+
+impl PathTracer {
+    /// Normal, albedo and distance of the first surface the ray hits.
+    pub fn features(&self, scene: &Scene, ray: &Ray) -> Features {
+        let hit = match scene.intersect(ray, f32::INFINITY) {
+            Some(hit) => hit,
+            None => return Features::default(),
+        };
+
+        let footprint = hit.t * ray.spread * hit.uv_density;
+        Features {
+            normal: hit.normal,
+            albedo: scene.albedo(&hit, footprint),
+            depth: hit.t,
+        }
+    }
+
+    /// Russian roulette past `rr_depth`, None when the path is terminated.
+    fn roulette(&self, depth: u32, throughput: [f32; 3], rng: &mut Rng) -> Option<[f32; 3]> {
+        if depth < self.rr_depth {
+            return Some(throughput);
+        }
+
+        let survive = throughput[0]
+            .max(throughput[1])
+            .max(throughput[2])
+            .min(0.95);
+        if rng.next_f32() >= survive {
+            return None;
+        }
+        Some(mul(throughput, 1. / survive))
+    }
+}
+
+impl Default for PathTracer {
+    fn default() -> PathTracer {
+        PathTracer::new()
+    }
+}
+
+/// Point a path scatters at, on a surface or inside a medium.
+enum Vertex<'a> {
+    Surface {
+        hit: &'a Hit,
+        material: &'a Material,
+    },
+    Medium {
+        point: [f32; 3],
+        g: f32,
+        time: f32,
+    },
+}
+
+impl<'a> Vertex<'a> {
+    fn point(&self) -> [f32; 3] {
+        match self {
+            Vertex::Surface { hit, .. } => hit.point,
+            Vertex::Medium { point, .. } => *point,
+        }
+    }
+
+    /// Scattered radiance factor towards `wo` of light arriving from `wi`,
+    /// and the density of sampling `wi`.
+    fn eval(&self, wo: [f32; 3], wi: [f32; 3]) -> ([f32; 3], f32) {
+        match self {
+            Vertex::Surface { hit, material } => (
+                bsdf::eval(material, hit.normal, wo, wi),
+                bsdf::pdf(material, hit.normal, wo, wi),
+            ),
+            Vertex::Medium { g, .. } => {
+                let p = medium::phase(*g, -dot(wo, wi));
+                ([p, p, p], p)
+            }
+        }
+    }
+
+    fn spawn(&self, dir: [f32; 3]) -> Ray {
+        match self {
+            Vertex::Surface { hit, .. } => hit.spawn(dir),
+            Vertex::Medium { point, time, .. } => Ray {
+                time: *time,
+                ..Ray::new(*point, dir)
+            },
+        }
+    }
+}
+
+/// Next event estimation, one sample per light, of the emitters and of the
+/// environment.
+fn direct(scene: &Scene, vertex: &Vertex, wo: [f32; 3], rng: &mut Rng) -> [f32; 3] {
+    let p = vertex.point();
+    let mut direct = [0., 0., 0.];
+
+    for light in scene.lights.iter() {
+        if let Some(s) = light.sample(p, [rng.next_f32(), rng.next_f32()]) {
+            let l = light_sample(scene, vertex, wo, s, light.is_delta(), rng);
+            direct = add(direct, l);
+        }
+    }
+
+    let u = [rng.next_f32(), rng.next_f32(), rng.next_f32()];
+    if let Some(s) = scene.sample_emitter(p, u) {
+        direct = add(direct, light_sample(scene, vertex, wo, s, false, rng));
+    }
+
+    if !scene.environment.is_black() {
+        let (wi, le, pdf) = scene.environment.sample([rng.next_f32(), rng.next_f32()]);
+        let s = LightSample {
+            wi,
+            dist: f32::INFINITY,
+            radiance: le,
+            pdf,
+        };
+        direct = add(direct, light_sample(scene, vertex, wo, s, false, rng));
+    }
+
+    direct
+}
+
+/// Radiance scattered towards `wo` from one light sample, weighted against
+/// BSDF or phase sampling unless the light is a delta light.
+fn light_sample(
+    scene: &Scene,
+    vertex: &Vertex,
+    wo: [f32; 3],
+    sample: LightSample,
+    delta: bool,
+    rng: &mut Rng,
+) -> [f32; 3] {
+    let (f, pdf) = vertex.eval(wo, sample.wi);
+    if sample.pdf <= 0.0 || f == [0., 0., 0.] {
+        return [0., 0., 0.];
+    }
+    // stop short of emitters that are geometry themselves
+    let shadow = vertex.spawn(sample.wi);
+    let dist = sample.dist * (1. - 1e-3);
+    if scene.occluded(&shadow, dist) {
+        return [0., 0., 0.];
+    }
+
+    let mut tr = medium::transmittance(&scene.media, &shadow, dist, rng);
+    if let Some(fog) = scene.fog.as_ref() {
+        tr = mul(tr, fog.transmittance(&shadow, dist));
+    }
+
+    let w = if delta {
+        1.
+    } else {
+        power_heuristic(sample.pdf, pdf)
+    };
+    mul(comp_dot(comp_dot(f, sample.radiance), tr), w / sample.pdf)
+}
+
+/// Multiple importance sampling weight of a strategy with density `a`
+/// against one with density `b`.
+pub fn power_heuristic(a: f32, b: f32) -> f32 {
+    let (a2, b2) = (a * a, b * b);
+    if a2 + b2 > 0. {
+        a2 / (a2 + b2)
+    } else {
+        0.
+    }
+}
+
+pub fn cosine_hemisphere(n: [f32; 3], u1: f32, u2: f32) -> [f32; 3] {
+    let r = u1.sqrt();
+    let phi = 2. * PI * u2;
+    let (t, b) = basis(n);
+
+    let x = r * phi.cos();
+    let y = r * phi.sin();
+    let z = (1. - u1).max(0.).sqrt();
+
+    unit(add(add(mul(t, x), mul(b, y)), mul(n, z)))
+}
+
+#[cfg(test)]
+mod tests {
+    use common::model::environment::Environment;
+
+    use super::*;
+    use crate::tracer::Sphere;
+
+    /// Diffuse ball on a diffuse floor under the sky.
+    fn scene() -> Scene {
+        let mut scene = Scene::new();
+        scene.environment = Environment::sky();
+        let material = scene.push_material(Material::lambertian([0.8, 0.6, 0.4]));
+        for (name, center, radius) in [
+            ("ball", [0., 0., -3.], 1.),
+            ("floor", [0., -101., -3.], 100.),
+        ] {
+            let object = scene.object_id(name);
+            scene.spheres.push(Sphere {
+                center,
+                radius,
+                material,
+                motion: None,
+                object,
+            });
+        }
+        scene
+    }
+
+    #[test]
+    fn radiance_is_determined_by_the_seed() {
+        let scene = scene();
+        let tracer = PathTracer::new();
+        let ray = Ray::new([0., 0., 0.], unit([0.3, -0.2, -1.]));
+        let radiance = |seed| tracer.radiance(&scene, ray, &mut Rng::for_sample(seed, 7, 3));
+
+        assert_eq!(radiance(1), radiance(1));
+        assert_ne!(radiance(1), radiance(2));
+    }
+}
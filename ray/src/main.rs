@@ -1,8 +1,33 @@
+mod bsdf;
+mod bvh;
+mod camera;
+mod denoise;
+mod film;
+mod integrator;
+mod medium;
+mod render;
+mod sampler;
+mod tracer;
+
 use std::rc::Rc;
+use std::sync::Arc;
 
+use camera::Camera;
+use common::model::aov::{Aov, AovBuffers};
+use common::model::environment::Environment;
 use common::model::figure::*;
+use common::model::graph::SceneGraph;
+use common::model::light::Light;
 use common::model::mat::*;
+use common::model::material::Material;
+use common::model::primitives::{cuboid, plane};
+use common::model::procedural::{Pattern, Procedural};
+use common::model::scene as scene_file;
+use common::model::texture::TextureMap;
+use common::model::transform::Transform;
+use denoise::Denoiser;
 use draw::background::new;
+use film::{Display, Film};
 
 use geom::pt2;
 use image::ImageBuffer;
@@ -10,7 +35,9 @@ use nannou::color::*;
 use nannou::event::WindowEvent::*;
 use nannou::event::*;
 use nannou::*;
+use render::{Job, Renderer};
 use std::cell::RefCell;
+use tracer::{Motion, Scene, Sphere};
 use wgpu::Device;
 use wgpu::Texture;
 use winit::event::VirtualKeyCode::*;
@@ -20,6 +47,11 @@ struct Model {
     eye: [f32; 3],
     at: [f32; 3],
     up: [f32; 3],
+    /// Vertical field of view in degrees.
+    fov: f32,
+    /// Lens of the path traced camera, see `Camera`.
+    aperture: f32,
+    focus_distance: Option<f32>,
     width: f32,
     height: f32,
     camera: Mat4x4,
@@ -30,16 +62,41 @@ struct Model {
     mouse_y: f32,
     mouse_pressed: bool,
     alt: bool,
-    mesh: Mesh,
+    /// Objects of the scene, placed in world space by their nodes.
+    graph: SceneGraph,
+    mode: Mode,
+    scene: Arc<Scene>,
+    renderer: Renderer,
+    job: Option<Job>,
+    film: Film,
+    display: Display,
+    denoiser: Denoiser,
+    /// Denoised copy of the film after the last finished pass, when enabled.
+    denoised: Option<Film>,
+    denoise: bool,
 }
 
-#[derive(Debug, Copy, Clone)]
-struct Ray {
-    origin: [f32; 3],
-    dir: [f32; 3],
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Mode {
+    Preview,
+    PathTrace,
 }
 
+/// Eye, target and up vector of the built in scene.
+const DEMO_VIEW: ([f32; 3], [f32; 3], [f32; 3]) =
+    ([0., 0.0, 0.0], [0.45, 0.15, -1.3], [0., 1.0, 0.]);
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.first().map(String::as_str) == Some("render") {
+        if let Err(e) = render::run(&args[1..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     nannou::app(model).update(update).run();
 }
 
@@ -48,10 +105,75 @@ fn model(app: &App) -> Model {
     window.event(event).view(view).build().unwrap();
     let viewport = app.window_rect();
 
-    let eye = [0., 0.0, 0.0];
-    let at = [0.45, 0.15, -1.3];
-    let up = [0., 1.0, 0.];
+    // `ray scene.json` opens a scene file instead of the demo
+    let (camera, graph, scene) = match std::env::args().nth(1) {
+        Some(path) => {
+            let file = scene_file::load_scene(&path).unwrap_or_else(|e| {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(1);
+            });
+            (file.camera, file.graph.clone(), file_scene(&file))
+        }
+        None => {
+            let (eye, at, up) = DEMO_VIEW;
+            let camera = scene_file::Camera {
+                eye,
+                at,
+                up,
+                fov: 60.,
+                aperture: 0.,
+                focus_distance: None,
+            };
+            let mesh = demo_mesh();
+            let scene = demo_scene(&mesh);
+            (camera, SceneGraph::from(mesh), scene)
+        }
+    };
+    let (eye, at, up) = (camera.eye, camera.at, camera.up);
 
+    let texture = wgpu::TextureBuilder::new()
+        .size([viewport.w() as u32, viewport.h() as u32])
+        .format(wgpu::TextureFormat::Rgba8Unorm)
+        .usage(wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING)
+        .build(app.main_window().device());
+
+    Model {
+        texture,
+        eye,
+        at,
+        up,
+        fov: camera.fov,
+        aperture: camera.aperture,
+        focus_distance: camera.focus_distance,
+        width: viewport.w(),
+        height: viewport.h(),
+        camera: viewer(eye, at, up),
+        perspective_proj: perspective_projection(
+            camera.fov,
+            viewport.w() / viewport.h(),
+            -10.,
+            -1.,
+        ),
+        mouse_x_pressed: 0.0,
+        mouse_y_pressed: 0.0,
+        mouse_x: 0.0,
+        mouse_y: 0.0,
+        mouse_pressed: false,
+        alt: false,
+        graph,
+        mode: Mode::Preview,
+        scene: Arc::new(scene),
+        renderer: Renderer::new(),
+        job: None,
+        film: Film::new(viewport.w() as usize, viewport.h() as usize),
+        display: Display::new(),
+        denoiser: Denoiser::new(),
+        denoised: None,
+        denoise: false,
+    }
+}
+
+fn demo_mesh() -> Mesh {
     let mut mesh = Mesh::new();
 
     let axis = mesh.push_object("axis");
@@ -64,58 +186,111 @@ fn model(app: &App) -> Model {
     axis.push_edge(Edge::new_color(0, 2, RED).text("Y"));
     axis.push_edge(Edge::new_color(0, 3, RED).text("-Z"));
 
-    let cube = mesh.push_object("cube");
-    cube.push_vertex(Vertex::from([0.3, 0.3, -1.1, 1.]));
-    cube.push_vertex(Vertex::from([0.6, 0.3, -1.1, 1.]));
-    cube.push_vertex(Vertex::from([0.6, 0., -1.1, 1.]));
-    cube.push_vertex(Vertex::from([0.3, 0., -1.1, 1.]));
+    let mut cube = cuboid([0.3; 3], 1);
+    cube.transform(&translation_mat(0.45, 0.15, -1.25));
+    let marble = Procedural::new(
+        Pattern::Marble { turbulence: 1.5 },
+        [[1., 1., 1.], [0.35, 0.3, 0.3]],
+        7,
+    );
+    cube.set_material(
+        Material::pbr([0.9, 0.6, 0.2], 0.0, 0.4).with_albedo_map(TextureMap::Procedural(
+            Arc::new(marble.with_scale(15.).solid()),
+        )),
+    );
+    mesh.objects.insert(String::from("cube"), cube);
 
-    cube.push_vertex(Vertex::from([0.3, 0.3, -1.4, 1.]));
-    cube.push_vertex(Vertex::from([0.6, 0.3, -1.4, 1.]));
-    cube.push_vertex(Vertex::from([0.6, 0., -1.4, 1.]));
-    cube.push_vertex(Vertex::from([0.3, 0., -1.4, 1.]));
+    let mut floor = plane(3., 1);
+    floor.transform(&translation_mat(0.5, 0., -1.5));
+    floor.set_material(Material::default().with_albedo_map(TextureMap::Procedural(
+        Arc::new(Procedural::checker(8., [[1., 1., 1.], [0.2, 0.2, 0.2]]).solid()),
+    )));
+    mesh.objects.insert(String::from("floor"), floor);
 
-    cube.push_face(Face::new([0, 3, 2]));
-    cube.push_face(Face::new([2, 1, 0]));
+    mesh
+}
 
-    cube.push_face(Face::new([0, 1, 5]));
-    cube.push_face(Face::new([5, 4, 0]));
+/// Scene of the demo mesh with an extra moving analytic sphere, lit by a softbox
+/// above the cube and the sky.
+fn demo_scene(mesh: &Mesh) -> Scene {
+    let mut scene = Scene::from_mesh(mesh);
+    let sphere_material = scene.push_material(Material::metal([1., 0., 1.], 0.2));
+    // rolls along x while the shutter is open
+    let from = Transform::new().translate([0.1, 0.12, -1.25]);
+    let motion = scene.push_motion(Motion {
+        from,
+        to: from.translate([0.18, 0.12, -1.25]),
+    });
+    let object = scene.object_id("sphere");
+    scene.spheres.push(Sphere {
+        center: [0., 0., 0.],
+        radius: 0.12,
+        material: sphere_material,
+        motion: Some(motion),
+        object,
+    });
+
+    scene.lights.push(Light::Rect {
+        corner: [0.1, 0.8, -1.],
+        u: [0., 0., -0.4],
+        v: [0.6, 0., 0.],
+        radiance: [4., 4., 4.],
+    });
+    scene.environment = Environment::sky();
+    scene
+}
 
-    cube.push_face(Face::new([1, 2, 6]));
-    cube.push_face(Face::new([6, 5, 1]));
+/// Path traced scene of a loaded scene file.
+fn file_scene(file: &scene_file::Scene) -> Scene {
+    let mut scene = Scene::from_graph(&file.graph);
+    scene.lights = file.lights.clone();
+    scene.environment = file.environment.clone();
+    scene
+}
 
-    cube.push_face(Face::new([3, 7, 6]));
-    cube.push_face(Face::new([6, 2, 3]));
+fn file_camera(c: &scene_file::Camera) -> Camera {
+    let mut camera = Camera::new(c.eye, c.at, c.up, c.fov);
+    camera.aperture = c.aperture;
+    if let Some(focus) = c.focus_distance {
+        camera.focus_distance = focus;
+    }
+    camera
+}
 
-    cube.push_face(Face::new([0, 4, 7]));
-    cube.push_face(Face::new([7, 3, 0]));
+/// Path traced camera of the current view.
+fn view_camera(model: &Model) -> Camera {
+    file_camera(&scene_file::Camera {
+        eye: model.eye,
+        at: model.at,
+        up: model.up,
+        fov: model.fov,
+        aperture: model.aperture,
+        focus_distance: model.focus_distance,
+    })
+}
 
-    cube.push_face(Face::new([4, 5, 6]));
-    cube.push_face(Face::new([6, 7, 4]));
+/// Adds the default key light and sky.
+fn lit(mut scene: Scene) -> Scene {
+    scene.lights.push(Light::Directional {
+        dir: [-1., -1., -1.],
+        radiance: [2.5, 2.5, 2.5],
+    });
+    scene.environment = Environment::sky();
+    scene
+}
 
-    let texture = wgpu::TextureBuilder::new()
-        .size([viewport.w() as u32, viewport.h() as u32])
-        .format(wgpu::TextureFormat::Rgba8Unorm)
-        .usage(wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING)
-        .build(app.main_window().device());
+fn look(model: &mut Model) {
+    model.camera = viewer(model.eye, model.at, model.up);
+    restart(model);
+}
 
-    Model {
-        texture,
-        eye,
-        at,
-        up,
-        width: viewport.w(),
-        height: viewport.h(),
-        camera: viewer(eye, at, up),
-        perspective_proj: perspective_projection(60., viewport.w() / viewport.h(), -10., -1.),
-        mouse_x_pressed: 0.0,
-        mouse_y_pressed: 0.0,
-        mouse_x: 0.0,
-        mouse_y: 0.0,
-        mouse_pressed: false,
-        alt: false,
-        mesh,
+/// Drops the accumulated samples and any pass still in flight.
+fn restart(model: &mut Model) {
+    if let Some(job) = model.job.take() {
+        job.cancel();
     }
+    model.film.reset();
+    model.denoised = None;
 }
 
 fn event(_app: &App, model: &mut Model, event: WindowEvent) {
@@ -123,29 +298,69 @@ fn event(_app: &App, model: &mut Model, event: WindowEvent) {
         KeyPressed(key) => match key {
             Left => {
                 model.eye = rotate_y_around_p_vec3(0.001 * 180.0 / 3.1425, model.eye, model.at);
-                model.camera = viewer(model.eye, model.at, model.up);
+                look(model);
             }
             Right => {
                 model.eye = rotate_y_around_p_vec3(-0.001 * 180.0 / 3.1425, model.eye, model.at);
-                model.camera = viewer(model.eye, model.at, model.up);
+                look(model);
             }
             Up => {
                 if !model.alt {
                     model.eye = rotate_x_around_p_vec3(0.001 * 180.0 / 3.1425, model.eye, model.at);
-                    model.camera = viewer(model.eye, model.at, model.up);
+                    look(model);
                 } else {
                     model.eye = [model.eye[0], model.eye[1], model.eye[2] - 0.1];
-                    model.camera = viewer(model.eye, model.at, model.up);
+                    look(model);
                 }
             }
             Down => {
                 if !model.alt {
                     model.eye =
                         rotate_x_around_p_vec3(-0.001 * 180.0 / 3.1425, model.eye, model.at);
-                    model.camera = viewer(model.eye, model.at, model.up);
+                    look(model);
                 } else {
                     model.eye = [model.eye[0], model.eye[1], model.eye[2] + 0.1];
-                    model.camera = viewer(model.eye, model.at, model.up);
+                    look(model);
+                }
+            }
+            P => {
+                model.mode = match model.mode {
+                    Mode::Preview => Mode::PathTrace,
+                    Mode::PathTrace => Mode::Preview,
+                };
+                restart(model);
+            }
+            S => {
+                model.renderer.sampler.pattern = model.renderer.sampler.pattern.next();
+                restart(model);
+            }
+            F => {
+                model.renderer.sampler.filter = model.renderer.sampler.filter.next();
+                restart(model);
+            }
+            Equals => {
+                model.renderer.sampler.spp = (model.renderer.sampler.spp * 2).min(1024);
+                restart(model);
+            }
+            Minus => {
+                model.renderer.sampler.spp = (model.renderer.sampler.spp / 2).max(1);
+                restart(model);
+            }
+            PageUp => model.display.exposure += 0.5,
+            PageDown => model.display.exposure -= 0.5,
+            T => model.display.tone_map = model.display.tone_map.next(),
+            D => {
+                model.denoise = !model.denoise;
+                model.denoised = (model.denoise && model.film.passes > 0)
+                    .then(|| model.denoiser.apply(&model.film));
+            }
+            A => export_aovs(model),
+            X => {
+                let film = model.denoised.as_ref().unwrap_or(&model.film);
+                for path in ["render.png", "render.hdr"] {
+                    if let Err(e) = film.save(path, &model.display) {
+                        println!("Could not write {}: {}", path, e);
+                    }
                 }
             }
             LAlt => model.alt = true,
@@ -180,30 +395,64 @@ fn event(_app: &App, model: &mut Model, event: WindowEvent) {
                 model.eye =
                     rotate_y_around_p_vec3(x_diff / 1000000. * 180.0 / 3.1425, model.eye, model.at);
 
-                model.camera = viewer(model.eye, model.at, model.up);
+                look(model);
             }
         }
 
         Resized(dim) => {
-            model.perspective_proj = perspective_projection(60., dim[0] / dim[1], -100., -1.)
+            model.perspective_proj = perspective_projection(model.fov, dim[0] / dim[1], -100., -1.)
         }
 
         _ => {}
     }
 }
 
-fn update(_app: &App, _model: &mut Model, _update: Update) {}
+fn update(_app: &App, model: &mut Model, _update: Update) {
+    if model.mode == Mode::PathTrace {
+        render_pass(model);
+    }
+}
+
+/// Writes every AOV of the current view as `render.<aov>.png`, and with
+/// the path traced color to the layers of `render.exr`.
+fn export_aovs(model: &Model) {
+    let camera = view_camera(model);
+    let mut aovs = AovBuffers::new(model.film.width, model.film.height, &Aov::ALL);
+    model.renderer.render_aovs(&model.scene, &camera, &mut aovs);
 
-fn to_rgba(c: [f32; 4]) -> nannou::image::Rgba<u8> {
-    nannou::image::Rgba([
-        (c[0] * 255.) as u8,
-        (c[1] * 255.) as u8,
-        (c[2] * 255.) as u8,
-        (c[3] * 255.) as u8,
-    ])
+    if let Err(e) = aovs.save_images("render.png") {
+        println!("Could not write the AOV images: {}", e);
+    }
+    let beauty = (model.film.passes > 0).then(|| model.film.colors());
+    if let Err(e) = aovs.save_exr("render.exr", beauty.as_deref()) {
+        println!("Could not write render.exr: {}", e);
+    }
+}
+
+/// Keeps a background pass of `spp` samples per pixel running and merges
+/// the tiles it has finished into the film.
+fn render_pass(model: &mut Model) {
+    if model.job.is_none() {
+        let camera = view_camera(model);
+        model.job = Some(Job::start(
+            model.renderer,
+            model.scene.clone(),
+            camera,
+            &model.film,
+        ));
+    }
+
+    if let Some(job) = model.job.as_mut() {
+        if job.poll(&mut model.film) {
+            model.job = None;
+            if model.denoise {
+                model.denoised = Some(model.denoiser.apply(&model.film));
+            }
+        }
+    }
 }
 
-fn pixel(x: f32, y: f32) -> [f32; 4] {
+fn pixel(x: f32, y: f32, environment: &Environment) -> [f32; 4] {
     let ray_orig = [0., 0., 1.];
     let ray_dir = [x, y, -1.];
     let radius = 0.5;
@@ -218,7 +467,8 @@ fn pixel(x: f32, y: f32) -> [f32; 4] {
     let disc = b * b - 4. * a * c;
 
     if disc < 0.0 {
-        return [0., 0., 0., 1.];
+        let sky = environment.radiance(unit(ray_dir));
+        return [sky[0], sky[1], sky[2], 1.];
     }
 
     // closest to ray origin
@@ -249,38 +499,40 @@ fn view(app: &App, model: &Model, frame: Frame) {
 
     let viewport = app.window_rect();
 
-    let transform = Mat4x4::unit();
-
-    let mut new_mesh = model.mesh.clone();
+    let mut new_mesh = model.graph.to_mesh();
 
     new_mesh.set_camera(model.eye);
 
-    let objects = model.perspective_proj * model.camera * transform * &new_mesh;
+    let objects = model.perspective_proj * model.camera * &new_mesh;
 
     let width = viewport.w();
     let height = viewport.h();
 
     let ratio = width / height;
-    let fov = 60.;
+    let fov = model.fov;
     let scale = (fov * 0.5 * std::f32::consts::PI / 180.).tan();
 
     let camera_to_world = model.camera.transpose();
 
-    let mut imgbuf = image::ImageBuffer::<image::Rgba<u8>, _>::new(640, 480);
+    let film = model.denoised.as_ref().unwrap_or(&model.film);
+    let mut imgbuf = image::ImageBuffer::<image::Rgba<u8>, _>::new(width as u32, height as u32);
 
     for y in 0..(height as i32) {
         for x in 0..(width as i32) {
-            let p_ndc_x = (x as f32) / width;
-            let p_ndc_y = (y as f32) / height;
+            let p_ndc_x = (x as f32 + 0.5) / width;
+            let p_ndc_y = (y as f32 + 0.5) / height;
 
             let p_screen_x = (2.0 * p_ndc_x - 1.) * ratio;
             let p_screen_y = 1. - 2.0 * p_ndc_y;
 
-            let color = pixel(p_screen_x, p_screen_y);
+            let color = match model.mode {
+                Mode::Preview => pixel(p_screen_x, p_screen_y, &model.scene.environment),
+                Mode::PathTrace => film.pixel(x as usize, y as usize),
+            };
 
-            imgbuf.put_pixel(x as u32, y as u32, to_rgba(color));
+            imgbuf.put_pixel(x as u32, y as u32, model.display.to_rgba(color));
 
-            for (k, obj) in model.mesh.objects.iter() {
+            for (k, obj) in new_mesh.objects.iter() {
                 if k == "cube" {}
             }
         }
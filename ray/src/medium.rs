@@ -0,0 +1,338 @@
+use std::f32::consts::PI;
+
+use common::model::mat::*;
+use common::model::rng::Rng;
+use noise::{NoiseFn, Perlin};
+
+use crate::tracer::Ray;
+
+/// How the density of a medium, in [0, 1], varies inside its bounds.
+#[derive(Debug, Clone)]
+pub enum Density {
+    Homogeneous,
+    /// Perlin fBm, `scale` features per unit, with the thinnest parts cut
+    /// away by `threshold`.
+    Noise {
+        perlin: Box<Perlin>,
+        scale: f32,
+        octaves: u32,
+        threshold: f32,
+    },
+}
+
+/// Box of absorbing and scattering particles. The coefficients are per unit
+/// length at full density.
+#[derive(Debug, Clone)]
+pub struct Medium {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub sigma_a: [f32; 3],
+    pub sigma_s: [f32; 3],
+    /// Henyey-Greenstein asymmetry, 0 scatters evenly, positive forwards.
+    pub g: f32,
+    pub density: Density,
+}
+
+impl Medium {
+    pub fn homogeneous(
+        min: [f32; 3],
+        max: [f32; 3],
+        sigma_a: [f32; 3],
+        sigma_s: [f32; 3],
+    ) -> Medium {
+        Medium {
+            min,
+            max,
+            sigma_a,
+            sigma_s,
+            g: 0.,
+            density: Density::Homogeneous,
+        }
+    }
+
+    pub fn noise(self, scale: f32, seed: u32) -> Medium {
+        Medium {
+            density: Density::Noise {
+                perlin: Box::new(Perlin::new(seed)),
+                scale,
+                octaves: 4,
+                threshold: 0.1,
+            },
+            ..self
+        }
+    }
+
+    pub fn with_g(self, g: f32) -> Medium {
+        Medium { g, ..self }
+    }
+
+    pub fn density(&self, p: [f32; 3]) -> f32 {
+        match &self.density {
+            Density::Homogeneous => 1.,
+            Density::Noise {
+                perlin,
+                scale,
+                octaves,
+                threshold,
+            } => {
+                let mut sum = 0.;
+                let mut amplitude = 0.5;
+                let mut frequency = *scale;
+                for _ in 0..*octaves {
+                    sum += amplitude * perlin.get(p.map(|c| (c * frequency) as f64)) as f32;
+                    amplitude *= 0.5;
+                    frequency *= 2.;
+                }
+                ((sum * 0.5 + 0.5 - threshold) / (1. - threshold)).clamp(0., 1.)
+            }
+        }
+    }
+
+    /// Extinction bound over the whole box.
+    fn majorant(&self) -> f32 {
+        let t = add(self.sigma_a, self.sigma_s);
+        t[0].max(t[1]).max(t[2])
+    }
+
+    /// Part of the ray within the box, clipped to `[0, t_max)`.
+    fn span(&self, ray: &Ray, t_max: f32) -> Option<(f32, f32)> {
+        let mut t0: f32 = 0.;
+        let mut t1 = t_max;
+        for i in 0..3 {
+            let inv = 1. / ray.dir[i];
+            let a = (self.min[i] - ray.origin[i]) * inv;
+            let b = (self.max[i] - ray.origin[i]) * inv;
+            t0 = t0.max(a.min(b));
+            t1 = t1.min(a.max(b));
+        }
+        (t0 < t1).then_some((t0, t1))
+    }
+}
+
+/// Real collision found by `sample_interaction`.
+#[derive(Debug, Copy, Clone)]
+pub struct Interaction {
+    pub t: f32,
+    /// Medium the ray scatters in.
+    pub medium: usize,
+}
+
+/// Media a ray crosses before some distance.
+struct Crossing {
+    /// Part of the ray within each medium.
+    spans: Vec<Option<(f32, f32)>>,
+    /// Sum of the majorants of the crossed media.
+    majorant: f32,
+    start: f32,
+    end: f32,
+}
+
+impl Crossing {
+    fn new(media: &[Medium], ray: &Ray, t_max: f32) -> Crossing {
+        let spans: Vec<_> = media.iter().map(|m| m.span(ray, t_max)).collect();
+        let mut crossing = Crossing {
+            spans,
+            majorant: 0.,
+            start: f32::INFINITY,
+            end: 0.,
+        };
+        for (m, span) in media.iter().zip(crossing.spans.iter()) {
+            if let Some((t0, t1)) = span {
+                crossing.majorant += m.majorant();
+                crossing.start = crossing.start.min(*t0);
+                crossing.end = crossing.end.max(*t1);
+            }
+        }
+        crossing
+    }
+
+    /// Absorption and scattering coefficients of all media at `p = ray.at(t)`.
+    fn coefficients(&self, media: &[Medium], p: [f32; 3], t: f32) -> Vec<([f32; 3], [f32; 3])> {
+        media
+            .iter()
+            .zip(self.spans.iter())
+            .map(|(m, span)| match span {
+                Some((t0, t1)) if t >= *t0 && t < *t1 => {
+                    let d = m.density(p);
+                    (mul(m.sigma_a, d), mul(m.sigma_s, d))
+                }
+                _ => ([0., 0., 0.], [0., 0., 0.]),
+            })
+            .collect()
+    }
+}
+
+/// Delta tracking of the first scattering event before `t_max`. Returns the
+/// event, if any, and the factor the throughput is multiplied by. Colored
+/// coefficients are handled with spectral tracking, where the collision type
+/// is picked on the average channel and the others are reweighted.
+pub fn sample_interaction(
+    media: &[Medium],
+    ray: &Ray,
+    t_max: f32,
+    rng: &mut Rng,
+) -> (Option<Interaction>, [f32; 3]) {
+    let mut weight = [1., 1., 1.];
+    let crossing = Crossing::new(media, ray, t_max);
+    let majorant = crossing.majorant;
+    if majorant <= 0. {
+        return (None, weight);
+    }
+
+    let mut t = crossing.start;
+    loop {
+        t -= (1. - rng.next_f32()).ln() / majorant;
+        if t >= crossing.end {
+            return (None, weight);
+        }
+
+        let p = ray.at(t);
+        let sigma = crossing.coefficients(media, p, t);
+        let sigma_s = sigma.iter().fold([0., 0., 0.], |s, c| add(s, c.1));
+        let sigma_t = sigma.iter().fold(sigma_s, |s, c| add(s, c.0));
+        let sigma_n = [0, 1, 2].map(|i| majorant - sigma_t[i]);
+
+        let (avg_s, avg_n) = (average(sigma_s), average(sigma_n));
+        if avg_s + avg_n <= 0. {
+            return (None, [0., 0., 0.]);
+        }
+
+        let p_scatter = avg_s / (avg_s + avg_n);
+        if rng.next_f32() < p_scatter {
+            weight = comp_dot(weight, mul(sigma_s, 1. / (majorant * p_scatter)));
+
+            // the medium scattering is picked in proportion to its share
+            let mut pick = rng.next_f32() * avg_s;
+            let medium = sigma
+                .iter()
+                .position(|c| {
+                    pick -= average(c.1);
+                    pick < 0.
+                })
+                .unwrap_or(media.len() - 1);
+
+            return (Some(Interaction { t, medium }), weight);
+        }
+        weight = comp_dot(weight, mul(sigma_n, 1. / (majorant * (1. - p_scatter))));
+    }
+}
+
+/// Ratio tracking estimate of the transmittance along the ray up to `t_max`.
+pub fn transmittance(media: &[Medium], ray: &Ray, t_max: f32, rng: &mut Rng) -> [f32; 3] {
+    let mut tr = [1., 1., 1.];
+    let crossing = Crossing::new(media, ray, t_max);
+    let majorant = crossing.majorant;
+    if majorant <= 0. {
+        return tr;
+    }
+
+    let mut t = crossing.start;
+    loop {
+        t -= (1. - rng.next_f32()).ln() / majorant;
+        if t >= crossing.end {
+            return tr;
+        }
+
+        let sigma = crossing.coefficients(media, ray.at(t), t);
+        let sigma_t = sigma
+            .iter()
+            .fold([0., 0., 0.], |s, c| add(s, add(c.0, c.1)));
+        tr = comp_dot(tr, [0, 1, 2].map(|i| 1. - sigma_t[i] / majorant));
+
+        // Russian roulette once little light is left
+        let max = tr[0].max(tr[1]).max(tr[2]);
+        if max < 0.1 {
+            if rng.next_f32() >= max {
+                return [0., 0., 0.];
+            }
+            tr = mul(tr, 1. / max);
+        }
+    }
+}
+
+/// Henyey-Greenstein phase function for the angle between the incoming
+/// travel direction and the scattered one.
+pub fn phase(g: f32, cos: f32) -> f32 {
+    let denom = 1. + g * g - 2. * g * cos;
+    (1. - g * g) / (4. * PI * denom * denom.max(1e-8).sqrt())
+}
+
+/// Direction scattered from a ray travelling along `dir`, `u` in [0, 1)^2.
+/// Sampled exactly, so the density equals `phase`.
+pub fn sample_phase(g: f32, dir: [f32; 3], u: [f32; 2]) -> ([f32; 3], f32) {
+    let cos = if g.abs() < 1e-3 {
+        1. - 2. * u[0]
+    } else {
+        let s = (1. - g * g) / (1. + g - 2. * g * u[0]);
+        (1. + g * g - s * s) / (2. * g)
+    };
+    let sin = (1. - cos * cos).max(0.).sqrt();
+    let phi = 2. * PI * u[1];
+
+    let (t, b) = basis(dir);
+    let wi = unit(add(
+        add(mul(t, sin * phi.cos()), mul(b, sin * phi.sin())),
+        mul(dir, cos),
+    ));
+    (wi, phase(g, cos))
+}
+
+/// Haze filling the whole scene with a density falling off exponentially
+/// with height. It does not scatter light from the lights, it fades what is
+/// seen towards a flat `color` instead.
+#[derive(Debug, Copy, Clone)]
+pub struct Fog {
+    pub color: [f32; 3],
+    /// Extinction per unit length at `height`.
+    pub density: f32,
+    /// Rate the density decays with height, 0 for uniform fog.
+    pub falloff: f32,
+    pub height: f32,
+}
+
+impl Fog {
+    pub fn new(color: [f32; 3], density: f32) -> Fog {
+        Fog {
+            color,
+            density,
+            falloff: 0.,
+            height: 0.,
+        }
+    }
+
+    pub fn with_falloff(self, falloff: f32, height: f32) -> Fog {
+        Fog {
+            falloff,
+            height,
+            ..self
+        }
+    }
+
+    /// Fraction of the light crossing `dist` along the ray, `dist` may be infinite.
+    pub fn transmittance(&self, ray: &Ray, dist: f32) -> f32 {
+        let start = self.density * (-self.falloff * (ray.origin[1] - self.height)).exp();
+        // no fog, which an infinite `dist` must not turn into any
+        if start <= 0. {
+            return 1.;
+        }
+        let k = self.falloff * ray.dir[1];
+
+        // integral of the density along the ray
+        let depth = if k.abs() < 1e-5 {
+            start * dist
+        } else if dist.is_infinite() {
+            if k > 0. {
+                start / k
+            } else {
+                f32::INFINITY
+            }
+        } else {
+            start * (1. - (-k * dist).exp()) / k
+        };
+        (-depth).exp()
+    }
+}
+
+fn average(c: [f32; 3]) -> f32 {
+    (c[0] + c[1] + c[2]) / 3.
+}
@@ -0,0 +1,614 @@
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use common::model::aov::{Aov, AovBuffers};
+use common::model::environment::{EnvMap, Environment};
+use common::model::mat::*;
+use common::model::rng::Rng;
+use common::model::scene as scene_file;
+use common::model::wavefront::load_obj;
+
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+
+use crate::camera::Camera;
+use crate::denoise::Denoiser;
+use crate::film::{Display, Features, Film};
+use crate::integrator::PathTracer;
+use crate::medium::{Fog, Medium};
+use crate::sampler::PixelSampler;
+use crate::tracer::{Ray, Scene};
+
+/// Side of the square tiles a frame is split into.
+pub const TILE_SIZE: usize = 32;
+
+/// Pixel rectangle `[x0, x1) x [y0, y1)` of the film.
+#[derive(Debug, Copy, Clone)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+/// Weighted sample sums of one tile, row major.
+#[derive(Debug, Clone)]
+pub struct TileResult {
+    pub tile: Tile,
+    pub sum: Vec<[f32; 3]>,
+    pub weight: Vec<f32>,
+    /// Unweighted feature sums of the `samples` samples of each pixel.
+    pub features: Vec<Features>,
+    pub samples: u32,
+}
+
+pub fn tiles(width: usize, height: usize) -> Vec<Tile> {
+    let mut tiles = vec![];
+    for y0 in (0..height).step_by(TILE_SIZE) {
+        for x0 in (0..width).step_by(TILE_SIZE) {
+            tiles.push(Tile {
+                x0,
+                y0,
+                x1: (x0 + TILE_SIZE).min(width),
+                y1: (y0 + TILE_SIZE).min(height),
+            });
+        }
+    }
+    tiles
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Renderer {
+    pub tracer: PathTracer,
+    pub sampler: PixelSampler,
+    pub seed: u64,
+    /// Worker threads, 0 uses every core.
+    pub threads: usize,
+}
+
+impl Renderer {
+    pub fn new() -> Renderer {
+        Renderer {
+            tracer: PathTracer::new(),
+            sampler: PixelSampler::new(),
+            seed: 0,
+            threads: 0,
+        }
+    }
+
+    /// Traces `spp` samples for every pixel of the tile. Each sample has its
+    /// own generator, so the result does not depend on which thread runs it.
+    pub fn render_tile(
+        &self,
+        scene: &Scene,
+        camera: &Camera,
+        (width, height): (usize, usize),
+        pass: u32,
+        tile: Tile,
+    ) -> TileResult {
+        let ratio = width as f32 / height as f32;
+        let spp = self.sampler.spp;
+        let spread = camera.pixel_spread(height);
+        let len = (tile.x1 - tile.x0) * (tile.y1 - tile.y0);
+
+        let mut result = TileResult {
+            tile,
+            sum: Vec::with_capacity(len),
+            weight: Vec::with_capacity(len),
+            features: Vec::with_capacity(len),
+            samples: spp,
+        };
+
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                let pixel = (y * width + x) as u64;
+                // one offset per pixel, kept over the passes
+                let mut scramble = Rng::for_sample(self.seed, pixel, u64::MAX);
+                let scramble = [scramble.next_f32(), scramble.next_f32()];
+                let mut sum = [0., 0., 0.];
+                let mut total = 0.;
+                let mut features = Features::default();
+
+                for i in 0..spp {
+                    let index = pass * spp + i;
+                    let mut rng = Rng::for_sample(self.seed, pixel, index as u64);
+                    let (offset, weight) = self.sampler.sample(index, scramble, &mut rng);
+
+                    let p_ndc_x = (x as f32 + 0.5 + offset[0]) / width as f32;
+                    let p_ndc_y = (y as f32 + 0.5 + offset[1]) / height as f32;
+
+                    let p_screen_x = (2.0 * p_ndc_x - 1.) * ratio;
+                    let p_screen_y = 1. - 2.0 * p_ndc_y;
+
+                    let lens = [rng.next_f32(), rng.next_f32()];
+                    let mut ray = camera.sample_ray(p_screen_x, p_screen_y, lens, rng.next_f32());
+                    ray.spread = spread;
+                    features = features.add(self.tracer.features(scene, &ray));
+                    let color = self.tracer.radiance(scene, ray, &mut rng);
+                    sum = add(sum, mul(color, weight));
+                    total += weight;
+                }
+
+                result.sum.push(sum);
+                result.weight.push(total);
+                result.features.push(features);
+            }
+        }
+
+        result
+    }
+
+    /// Fills the AOVs with what is seen through the pixel centers in the
+    /// middle of the shutter interval. Motion spans the whole interval.
+    pub fn render_aovs(&self, scene: &Scene, camera: &Camera, aovs: &mut AovBuffers) {
+        let size = (aovs.width, aovs.height);
+        let ratio = size.0 as f32 / size.1 as f32;
+        let spread = camera.pixel_spread(size.1);
+
+        let samples: Vec<Option<AovSample>> = (0..size.0 * size.1)
+            .into_par_iter()
+            .map(|i| {
+                let x = ((i % size.0) as f32 + 0.5) / size.0 as f32;
+                let y = ((i / size.0) as f32 + 0.5) / size.1 as f32;
+                let mut ray = camera.sample_ray((2. * x - 1.) * ratio, 1. - 2. * y, [0., 0.], 0.5);
+                ray.spread = spread;
+                aov_sample(scene, camera, &ray, size)
+            })
+            .collect();
+
+        aovs.clear();
+        for (i, sample) in samples.into_iter().enumerate() {
+            let (x, y) = (i % size.0, i / size.0);
+            if let Some(s) = sample {
+                aovs.set(Aov::Depth, x, y, &[s.depth]);
+                aovs.set(Aov::Normal, x, y, &s.normal);
+                aovs.set(Aov::Albedo, x, y, &s.albedo);
+                aovs.set(Aov::ObjectId, x, y, &[s.object as f32 + 1.]);
+                aovs.set(Aov::Uv, x, y, &s.uv);
+                aovs.set(Aov::Motion, x, y, &s.motion);
+            }
+        }
+    }
+
+    /// Renders the tiles of one pass across the worker threads, handing each
+    /// tile to `sink` as soon as it is done. Tiles are skipped once `cancel`
+    /// is set.
+    pub fn render_tiles<F: Fn(TileResult) + Sync>(
+        &self,
+        scene: &Scene,
+        camera: &Camera,
+        size: (usize, usize),
+        pass: u32,
+        cancel: &AtomicBool,
+        sink: F,
+    ) {
+        let work = || {
+            tiles(size.0, size.1).into_par_iter().for_each(|tile| {
+                if !cancel.load(Ordering::Relaxed) {
+                    sink(self.render_tile(scene, camera, size, pass, tile));
+                }
+            })
+        };
+
+        if self.threads == 0 {
+            work();
+        } else {
+            match ThreadPoolBuilder::new().num_threads(self.threads).build() {
+                Ok(pool) => pool.install(work),
+                Err(_) => work(),
+            }
+        }
+    }
+
+    /// Adds one pass of `spp` samples per pixel to the film. `progress` is
+    /// called with the number of finished and total tiles.
+    pub fn render_pass<F: FnMut(usize, usize)>(
+        &self,
+        scene: &Scene,
+        camera: &Camera,
+        film: &mut Film,
+        mut progress: F,
+    ) {
+        let size = (film.width, film.height);
+        let total = tiles(size.0, size.1).len();
+        let pass = film.passes;
+        let cancel = AtomicBool::new(false);
+        let (sender, receiver) = mpsc::channel();
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                self.render_tiles(scene, camera, size, pass, &cancel, |tile| {
+                    sender.send(tile).ok();
+                })
+            });
+
+            for (done, tile) in receiver.iter().enumerate() {
+                film.merge(&tile);
+                progress(done + 1, total);
+            }
+        });
+
+        film.passes += 1;
+    }
+}
+
+/// AOV values of the surface a camera ray sees.
+#[derive(Debug, Copy, Clone)]
+struct AovSample {
+    depth: f32,
+    normal: [f32; 3],
+    albedo: [f32; 3],
+    object: usize,
+    uv: [f32; 2],
+    motion: [f32; 2],
+}
+
+fn aov_sample(
+    scene: &Scene,
+    camera: &Camera,
+    ray: &Ray,
+    size: (usize, usize),
+) -> Option<AovSample> {
+    let hit = scene.intersect(ray, f32::INFINITY)?;
+    let (_, depth) = camera.project(hit.point, size)?;
+
+    let (open, close) = camera.shutter;
+    let motion = hit
+        .motion
+        .and_then(|m| {
+            let motion = &scene.motions[m];
+            let start = camera
+                .project(motion.displace(hit.point, hit.time, open)?, size)?
+                .0;
+            let end = camera
+                .project(motion.displace(hit.point, hit.time, close)?, size)?
+                .0;
+            Some([end[0] - start[0], end[1] - start[1]])
+        })
+        .unwrap_or([0., 0.]);
+
+    Some(AovSample {
+        depth,
+        normal: hit.normal,
+        albedo: scene.albedo(&hit, hit.t * ray.spread * hit.uv_density),
+        object: hit.object,
+        uv: hit.uv,
+        motion,
+    })
+}
+
+impl Default for Renderer {
+    fn default() -> Renderer {
+        Renderer::new()
+    }
+}
+
+/// Progressive render running on a background thread. Finished tiles are
+/// picked up with `poll` so the window can show them as they arrive.
+pub struct Job {
+    receiver: Receiver<TileResult>,
+    remaining: usize,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Job {
+    pub fn start(renderer: Renderer, scene: Arc<Scene>, camera: Camera, film: &Film) -> Job {
+        let size = (film.width, film.height);
+        let pass = film.passes;
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+
+        let flag = cancel.clone();
+        thread::spawn(move || {
+            renderer.render_tiles(&scene, &camera, size, pass, &flag, |tile| {
+                sender.send(tile).ok();
+            })
+        });
+
+        Job {
+            receiver,
+            remaining: tiles(size.0, size.1).len(),
+            cancel,
+        }
+    }
+
+    /// Merges the tiles finished so far, returns true once the pass is complete.
+    pub fn poll(&mut self, film: &mut Film) -> bool {
+        while let Ok(tile) = self.receiver.try_recv() {
+            film.merge(&tile);
+            self.remaining -= 1;
+        }
+
+        if self.remaining == 0 {
+            film.passes += 1;
+        }
+        self.remaining == 0
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Settings of `ray render`.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub scene: Option<String>,
+    pub width: usize,
+    pub height: usize,
+    pub spp: u32,
+    pub out: String,
+    pub seed: u64,
+    pub exposure: f32,
+    pub threads: usize,
+    /// `sky`, `black` or an equirectangular `.hdr` file.
+    pub env: Option<String>,
+    /// Lens radius, 0 keeps everything in focus. Overrides the scene file.
+    pub aperture: Option<f32>,
+    /// Focus distance, None focuses on the point the camera looks at.
+    pub focus: Option<f32>,
+    /// Time the shutter stays open, objects move between 0 and 1.
+    pub shutter: f32,
+    /// Extinction of a height fog thinning out above the ground.
+    pub fog: Option<f32>,
+    /// Extinction of a noise smoke filling the bounds of the scene.
+    pub smoke: Option<f32>,
+    /// Runs the denoiser over the frame before saving it.
+    pub denoise: bool,
+    /// Written next to the frame, or as layers of an `.exr` frame.
+    pub aovs: Vec<Aov>,
+}
+
+pub const USAGE: &str = "usage: ray render [scene.obj|scene.json] [--width N] [--height N] \
+[--spp N] [--seed N] [--threads N] [--exposure STOPS] [--env sky|black|map.hdr] [--aperture R] [--focus D] \
+[--shutter T] [--fog DENSITY] [--smoke DENSITY] \
+[--denoise] [--aov all|depth,normal,albedo,id,uv,motion] \
+[--out frame.png|frame.hdr|frame.exr]";
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            scene: None,
+            width: 640,
+            height: 480,
+            spp: 16,
+            out: String::from("frame.png"),
+            seed: 0,
+            exposure: 0.,
+            threads: 0,
+            env: None,
+            aperture: None,
+            focus: None,
+            shutter: 0.,
+            fog: None,
+            smoke: None,
+            denoise: false,
+            aovs: vec![],
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                if options.scene.is_some() {
+                    return Err(format!("unexpected argument {}\n{}", arg, USAGE));
+                }
+                options.scene = Some(arg.clone());
+                continue;
+            }
+            if arg == "--denoise" {
+                options.denoise = true;
+                continue;
+            }
+
+            let value = args
+                .next()
+                .ok_or(format!("missing value for {}\n{}", arg, USAGE))?;
+
+            match arg.as_str() {
+                "--width" => options.width = parse(arg, value)?,
+                "--height" => options.height = parse(arg, value)?,
+                "--spp" => options.spp = parse(arg, value)?,
+                "--seed" => options.seed = parse(arg, value)?,
+                "--exposure" => options.exposure = parse(arg, value)?,
+                "--threads" => options.threads = parse(arg, value)?,
+                "--env" => options.env = Some(value.clone()),
+                "--aperture" => options.aperture = Some(parse(arg, value)?),
+                "--focus" => options.focus = Some(parse(arg, value)?),
+                "--shutter" => options.shutter = parse(arg, value)?,
+                "--fog" => options.fog = Some(parse(arg, value)?),
+                "--smoke" => options.smoke = Some(parse(arg, value)?),
+                "--aov" => options.aovs = Aov::parse_list(value)?,
+                "--out" => options.out = value.clone(),
+                _ => return Err(format!("unknown option {}\n{}", arg, USAGE)),
+            }
+        }
+
+        if options.width == 0 || options.height == 0 || options.spp == 0 {
+            return Err(String::from("width, height and spp must be positive"));
+        }
+        if options.aperture.unwrap_or(0.) < 0. || options.shutter < 0. {
+            return Err(String::from("aperture and shutter must not be negative"));
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse<T: FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {} for {}", value, arg))
+}
+
+/// Renders a frame without opening a window.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let options = Options::parse(args)?;
+
+    let (mut scene, mut camera) = match options.scene.as_ref() {
+        None => {
+            let (eye, at, up) = crate::DEMO_VIEW;
+            (
+                crate::demo_scene(&crate::demo_mesh()),
+                Camera::new(eye, at, up, 60.),
+            )
+        }
+        Some(path) if path.to_ascii_lowercase().ends_with(".json") => {
+            let file = scene_file::load_scene(path).map_err(|e| format!("{}: {}", path, e))?;
+            (crate::file_scene(&file), crate::file_camera(&file.camera))
+        }
+        Some(path) => {
+            let mesh = load_obj(path).map_err(|e| format!("{}: {}", path, e))?;
+            let scene = crate::lit(Scene::from_mesh(&mesh));
+            let camera = frame(&scene);
+            (scene, camera)
+        }
+    };
+
+    if let Some(aperture) = options.aperture {
+        camera.aperture = aperture;
+    }
+    camera.shutter = (0., options.shutter);
+    if let Some(focus) = options.focus {
+        camera.focus_distance = focus;
+    }
+
+    match options.env.as_deref() {
+        None => {}
+        Some("sky") => scene.environment = Environment::sky(),
+        Some("black") => scene.environment = Environment::default(),
+        Some(path) => {
+            let map = EnvMap::load(path).map_err(|e| format!("{}: {}", path, e))?;
+            scene.environment = Environment::Map(Arc::new(map));
+        }
+    }
+
+    if let Some(density) = options.fog {
+        let haze = scene.environment.radiance([1., 0., 0.]);
+        scene.fog = Some(Fog::new(haze, density).with_falloff(2., 0.));
+    }
+    if let (Some(density), Some((min, max))) = (options.smoke, bounds(&scene)) {
+        let smoke = Medium::homogeneous(min, max, [density * 0.1; 3], [density * 0.9; 3]);
+        scene
+            .media
+            .push(smoke.noise(4., options.seed as u32).with_g(0.3));
+    }
+
+    let mut renderer = Renderer::new();
+    renderer.sampler.spp = options.spp;
+    renderer.seed = options.seed;
+    renderer.threads = options.threads;
+
+    let mut film = Film::new(options.width, options.height);
+    let display = Display {
+        exposure: options.exposure,
+        ..Display::new()
+    };
+
+    let start = Instant::now();
+    renderer.render_pass(&scene, &camera, &mut film, progress_bar);
+    eprintln!();
+
+    if options.denoise {
+        film = Denoiser::new().apply(&film);
+    }
+
+    let mut aovs = AovBuffers::new(options.width, options.height, &options.aovs);
+    if !aovs.is_empty() {
+        renderer.render_aovs(&scene, &camera, &mut aovs);
+    }
+
+    let exr = options.out.to_ascii_lowercase().ends_with(".exr");
+    if exr {
+        aovs.save_exr(&options.out, Some(&film.colors()))
+            .map_err(|e| format!("{}: {}", options.out, e))?;
+    } else {
+        film.save(&options.out, &display)
+            .map_err(|e| format!("{}: {}", options.out, e))?;
+        aovs.save_images(&options.out)
+            .map_err(|e| format!("{}: {}", options.out, e))?;
+    }
+
+    eprintln!(
+        "Rendered {}x{} at {} spp to {} in {:.2}s",
+        options.width,
+        options.height,
+        options.spp,
+        options.out,
+        start.elapsed().as_secs_f32()
+    );
+
+    Ok(())
+}
+
+/// Camera looking at the bounding box of the scene triangles from the front.
+fn frame(scene: &Scene) -> Camera {
+    let (min, max) = match bounds(scene) {
+        Some(bounds) => bounds,
+        None => return Camera::new([0., 0., 1.], [0., 0., 0.], [0., 1., 0.], 60.),
+    };
+
+    let center = mul(add(min, max), 0.5);
+    let radius = dot(diff(max, min), diff(max, min)).sqrt() * 0.5;
+    let eye = add(center, [0., radius * 0.5, radius * 2.]);
+
+    Camera::new(eye, center, [0., 1., 0.], 60.)
+}
+
+/// Bounding box of the scene triangles and instances, None without any.
+fn bounds(scene: &Scene) -> Option<([f32; 3], [f32; 3])> {
+    let bounds = scene.bounds();
+    (!bounds.is_empty()).then_some((bounds.min, bounds.max))
+}
+
+fn progress_bar(done: usize, total: usize) {
+    if done > 1 && done < total && (done - 1) * 100 / total == done * 100 / total {
+        return;
+    }
+
+    let width = 40;
+    let filled = done * width / total;
+    eprint!(
+        "\r[{}{}] {:>3}%",
+        "#".repeat(filled),
+        "-".repeat(width - filled),
+        done * 100 / total
+    );
+    std::io::stderr().flush().ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thread_count_does_not_change_the_film() {
+        let scene = crate::demo_scene(&crate::demo_mesh());
+        let (eye, at, up) = crate::DEMO_VIEW;
+        let camera = Camera::new(eye, at, up, 60.);
+
+        // several tiles, the last ones partial, over two passes
+        let render = |threads| {
+            let mut renderer = Renderer::new();
+            renderer.sampler.spp = 2;
+            renderer.seed = 7;
+            renderer.threads = threads;
+            let mut film = Film::new(2 * TILE_SIZE + 5, TILE_SIZE + 3);
+            for _ in 0..2 {
+                renderer.render_pass(&scene, &camera, &mut film, |_, _| {});
+            }
+            film
+        };
+
+        let (one, four) = (render(1), render(4));
+        assert_eq!(one.passes, four.passes);
+        assert_eq!(one.colors(), four.colors());
+        for (x, y) in (0..one.height).flat_map(|y| (0..one.width).map(move |x| (x, y))) {
+            assert_eq!(one.features(x, y), four.features(x, y));
+        }
+    }
+}
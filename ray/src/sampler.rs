@@ -0,0 +1,179 @@
+use common::model::rng::Rng;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Pattern {
+    /// Uniform random positions.
+    Random,
+    /// One jittered sample per cell of a sqrt(spp) x sqrt(spp) grid.
+    Jittered,
+    /// Halton sequence in bases 2 and 3.
+    Halton,
+    /// First two dimensions of the Sobol sequence.
+    Sobol,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    /// Mitchell-Netravali with B = C = 1/3.
+    Mitchell,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct PixelSampler {
+    pub spp: u32,
+    pub pattern: Pattern,
+    pub filter: Filter,
+}
+
+impl Pattern {
+    pub fn next(self) -> Pattern {
+        match self {
+            Pattern::Random => Pattern::Jittered,
+            Pattern::Jittered => Pattern::Halton,
+            Pattern::Halton => Pattern::Sobol,
+            Pattern::Sobol => Pattern::Random,
+        }
+    }
+}
+
+impl Filter {
+    pub fn next(self) -> Filter {
+        match self {
+            Filter::Box => Filter::Tent,
+            Filter::Tent => Filter::Gaussian,
+            Filter::Gaussian => Filter::Mitchell,
+            Filter::Mitchell => Filter::Box,
+        }
+    }
+
+    /// Half width of the filter footprint in pixels.
+    pub fn radius(&self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.,
+        }
+    }
+
+    pub fn weight(&self, dx: f32, dy: f32) -> f32 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, x: f32) -> f32 {
+        let r = self.radius();
+        let x = x.abs();
+        if x > r {
+            return 0.;
+        }
+
+        match self {
+            Filter::Box => 1.,
+            Filter::Tent => 1. - x / r,
+            Filter::Gaussian => {
+                let alpha = 2.;
+                (-alpha * x * x).exp() - (-alpha * r * r).exp()
+            }
+            Filter::Mitchell => {
+                let (b, c) = (1. / 3., 1. / 3.);
+                let x = 2. * x / r;
+                let w = if x < 1. {
+                    (12. - 9. * b - 6. * c) * x * x * x
+                        + (-18. + 12. * b + 6. * c) * x * x
+                        + (6. - 2. * b)
+                } else {
+                    (-b - 6. * c) * x * x * x
+                        + (6. * b + 30. * c) * x * x
+                        + (-12. * b - 48. * c) * x
+                        + (8. * b + 24. * c)
+                };
+                w / 6.
+            }
+        }
+    }
+}
+
+impl PixelSampler {
+    pub fn new() -> PixelSampler {
+        PixelSampler {
+            spp: 1,
+            pattern: Pattern::Jittered,
+            filter: Filter::Box,
+        }
+    }
+
+    /// Position of sample `index` relative to the pixel center, spread over
+    /// the filter footprint, and the filter weight of that position.
+    /// `scramble` is the offset of the pixel randomizing the low discrepancy
+    /// sequences, the same for all of its samples so they keep their spread.
+    pub fn sample(&self, index: u32, scramble: [f32; 2], rng: &mut Rng) -> ([f32; 2], f32) {
+        let u = match self.pattern {
+            Pattern::Random => [rng.next_f32(), rng.next_f32()],
+            Pattern::Jittered => {
+                let nx = (self.spp as f32).sqrt().ceil() as u32;
+                let ny = self.spp.div_ceil(nx);
+                let cell = index % self.spp;
+                [
+                    ((cell % nx) as f32 + rng.next_f32()) / nx as f32,
+                    ((cell / nx) as f32 + rng.next_f32()) / ny as f32,
+                ]
+            }
+            Pattern::Halton => rotate(
+                [radical_inverse(index, 2), radical_inverse(index, 3)],
+                scramble,
+            ),
+            Pattern::Sobol => rotate(
+                [
+                    index.reverse_bits() as f32 / 4294967296.,
+                    sobol_2(index) as f32 / 4294967296.,
+                ],
+                scramble,
+            ),
+        };
+
+        let r = self.filter.radius();
+        let offset = [(2. * u[0] - 1.) * r, (2. * u[1] - 1.) * r];
+
+        (offset, self.filter.weight(offset[0], offset[1]))
+    }
+}
+
+impl Default for PixelSampler {
+    fn default() -> PixelSampler {
+        PixelSampler::new()
+    }
+}
+
+// Cranley-Patterson rotation
+fn rotate(u: [f32; 2], scramble: [f32; 2]) -> [f32; 2] {
+    [(u[0] + scramble[0]).fract(), (u[1] + scramble[1]).fract()]
+}
+
+fn radical_inverse(mut i: u32, base: u32) -> f32 {
+    let inv = 1. / base as f32;
+    let mut f = inv;
+    let mut r = 0.;
+    while i > 0 {
+        r += (i % base) as f32 * f;
+        i /= base;
+        f *= inv;
+    }
+    r
+}
+
+/// Second Sobol dimension, generated by the primitive polynomial x + 1.
+fn sobol_2(mut i: u32) -> u32 {
+    let mut v = 1u32 << 31;
+    let mut r = 0;
+    while i != 0 {
+        if i & 1 == 1 {
+            r ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    r
+}
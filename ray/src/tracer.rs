@@ -0,0 +1,723 @@
+use common::model::environment::Environment;
+use common::model::figure::*;
+use common::model::graph::SceneGraph;
+use common::model::light::{Light, LightSample};
+use common::model::mat::*;
+use common::model::material::Material;
+use common::model::transform::Transform;
+
+use std::sync::{Arc, OnceLock};
+
+use crate::bvh::{Aabb, Bvh};
+use crate::medium::{Fog, Medium};
+
+const EPSILON: f32 = 1e-4;
+
+/// Tint of everything but instances.
+const WHITE: [f32; 3] = [1., 1., 1.];
+
+#[derive(Debug, Copy, Clone)]
+pub struct Ray {
+    pub origin: [f32; 3],
+    pub dir: [f32; 3],
+    /// Growth of the pixel footprint per unit of distance, 0 when unknown.
+    pub spread: f32,
+    /// Instant within the shutter interval, places moving objects.
+    pub time: f32,
+}
+
+impl Ray {
+    pub fn new(origin: [f32; 3], dir: [f32; 3]) -> Ray {
+        Ray {
+            origin,
+            dir,
+            spread: 0.,
+            time: 0.,
+        }
+    }
+
+    pub fn at(&self, t: f32) -> [f32; 3] {
+        add(self.origin, mul(self.dir, t))
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Hit {
+    pub point: [f32; 3],
+    /// Unit normal facing against the incoming ray.
+    pub normal: [f32; 3],
+    /// Whether the ray arrived on the outward side of the surface.
+    pub front_face: bool,
+    pub material: usize,
+    /// Distance along the ray.
+    pub t: f32,
+    pub uv: [f32; 2],
+    /// Texture space length of one unit of surface.
+    pub uv_density: f32,
+    /// Index into `Scene::triangles`, None for spheres and instances.
+    pub triangle: Option<usize>,
+    /// Time of the ray that found the hit.
+    pub time: f32,
+    /// Index into `Scene::objects`.
+    pub object: usize,
+    /// Index into `Scene::motions` when the surface moves.
+    pub motion: Option<usize>,
+    /// Color of the instance hit, multiplying the albedo.
+    pub tint: [f32; 3],
+}
+
+impl Hit {
+    /// Origin for secondary rays, nudged off the surface to avoid self intersection.
+    pub fn spawn(&self, dir: [f32; 3]) -> Ray {
+        let offset = if dot(dir, self.normal) < 0.0 {
+            -EPSILON
+        } else {
+            EPSILON
+        };
+        Ray {
+            time: self.time,
+            ..Ray::new(add(self.point, mul(self.normal, offset)), dir)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Sphere {
+    pub center: [f32; 3],
+    pub radius: f32,
+    pub material: usize,
+    /// Index into `Scene::motions`, the sphere is then in object space.
+    pub motion: Option<usize>,
+    pub object: usize,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Triangle {
+    pub v: [[f32; 3]; 3],
+    pub uv: Option<[[f32; 2]; 3]>,
+    pub material: usize,
+    /// Index into `Scene::motions`, the vertexes are then in object space.
+    pub motion: Option<usize>,
+    pub object: usize,
+}
+
+/// Triangles in object space shared by any number of instances.
+#[derive(Debug, Clone)]
+pub struct InstanceMesh {
+    pub triangles: Vec<Triangle>,
+    bvh: Bvh,
+}
+
+impl InstanceMesh {
+    /// Faces of an object, with `material` indexing `Scene::materials`.
+    pub fn new(obj: &Obj3D, material: usize) -> InstanceMesh {
+        let triangles: Vec<Triangle> = obj
+            .faces
+            .iter()
+            .map(|face| Triangle {
+                v: face.vertexes.map(|i| obj.vertexes[i].to_vec_3()),
+                uv: face.uvs,
+                material,
+                motion: None,
+                object: 0,
+            })
+            .collect();
+        let boxes: Vec<Aabb> = triangles.iter().map(|t| t.bounds()).collect();
+        InstanceMesh {
+            triangles,
+            bvh: Bvh::build(&boxes),
+        }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+}
+
+/// Placement of an `InstanceMesh`.
+#[derive(Debug, Copy, Clone)]
+pub struct Instance {
+    /// Index into `Scene::meshes`.
+    pub mesh: usize,
+    pub to_world: Mat4x4,
+    to_local: Mat4x4,
+    pub tint: [f32; 3],
+    pub object: usize,
+}
+
+impl Instance {
+    /// The ray in mesh space. Its direction is not unit length, so that
+    /// distances along it match those along the world ray.
+    fn localize(&self, ray: &Ray) -> Ray {
+        Ray {
+            origin: self.to_local.mul_point(ray.origin),
+            dir: self.to_local.mul_dir(ray.dir),
+            ..*ray
+        }
+    }
+}
+
+/// Object to world transform moving from `from` at time 0 to `to` at time 1.
+#[derive(Debug, Copy, Clone)]
+pub struct Motion {
+    pub from: Transform,
+    pub to: Transform,
+}
+
+impl Motion {
+    /// Object to world matrix at `time`.
+    pub fn at(&self, time: f32) -> Mat4x4 {
+        self.from.lerp(&self.to, time).to_mat()
+    }
+
+    /// Where the world point `p` at time `from` is at time `to`.
+    pub fn displace(&self, p: [f32; 3], from: f32, to: f32) -> Option<[f32; 3]> {
+        let local = self.at(from).inverse()?.mul_point(p);
+        Some(self.at(to).mul_point(local))
+    }
+
+    /// Ray in object space at the ray time, and the matrix taking object
+    /// space normals back to world space.
+    fn localize(&self, ray: &Ray) -> Option<(Ray, Mat4x4)> {
+        let to_local = self.at(ray.time).inverse()?;
+        let local = Ray {
+            origin: to_local.mul_point(ray.origin),
+            dir: to_local.mul_dir(ray.dir),
+            ..*ray
+        };
+        Some((local, to_local.transpose()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Scene {
+    pub spheres: Vec<Sphere>,
+    pub triangles: Vec<Triangle>,
+    pub lights: Vec<Light>,
+    pub materials: Vec<Material>,
+    pub environment: Environment,
+    pub motions: Vec<Motion>,
+    /// Names of the objects, primitives refer to them by index.
+    pub objects: Vec<String>,
+    /// Geometry placed by `instances`, found through bounding volume
+    /// hierarchies rather than one by one like `triangles`.
+    pub meshes: Vec<Arc<InstanceMesh>>,
+    pub instances: Vec<Instance>,
+    /// Participating media, the ray sees them through their boxes.
+    pub media: Vec<Medium>,
+    pub fog: Option<Fog>,
+    emitters: OnceLock<Emitters>,
+    /// Hierarchy over the world bounds of the instances.
+    instance_bvh: OnceLock<Bvh>,
+}
+
+/// Triangles with an emissive material, picked proportionally to their area
+/// for light sampling.
+#[derive(Debug, Clone)]
+struct Emitters {
+    triangles: Vec<usize>,
+    /// Running sum of the triangle areas.
+    cdf: Vec<f32>,
+    area: f32,
+}
+
+impl Sphere {
+    // object space rays of moving spheres are not unit length
+    fn intersect(&self, ray: &Ray, t_max: f32) -> Option<f32> {
+        let oc = diff(ray.origin, self.center);
+        let a = dot(ray.dir, ray.dir);
+        let b = dot(oc, ray.dir);
+        let c = dot(oc, oc) - self.radius * self.radius;
+        let disc = b * b - a * c;
+
+        if disc < 0.0 {
+            return None;
+        }
+
+        let sq = disc.sqrt();
+        [(-b - sq) / a, (-b + sq) / a]
+            .into_iter()
+            .find(|t| *t > EPSILON && *t < t_max)
+    }
+
+    /// Longitude/latitude mapping of the unit normal `n`.
+    fn surface(&self, n: [f32; 3]) -> ([f32; 2], f32) {
+        let uv = [
+            0.5 + n[2].atan2(n[0]) / (2. * std::f32::consts::PI),
+            0.5 + n[1].clamp(-1., 1.).asin() / std::f32::consts::PI,
+        ];
+        (
+            uv,
+            1. / (std::f32::consts::SQRT_2 * std::f32::consts::PI * self.radius),
+        )
+    }
+}
+
+impl Triangle {
+    // Moller-Trumbore, returns the distance and the barycentrics of v[1] and v[2]
+    fn intersect(&self, ray: &Ray, t_max: f32) -> Option<(f32, [f32; 2])> {
+        let e1 = diff(self.v[1], self.v[0]);
+        let e2 = diff(self.v[2], self.v[0]);
+        let p = cross(ray.dir, e2);
+        let det = dot(e1, p);
+
+        if det.abs() < 1e-8 {
+            return None;
+        }
+
+        let inv_det = 1. / det;
+        let s = diff(ray.origin, self.v[0]);
+        let u = dot(s, p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = cross(s, e1);
+        let v = dot(ray.dir, q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = dot(e2, q) * inv_det;
+        (t > EPSILON && t < t_max).then_some((t, [u, v]))
+    }
+
+    /// Interpolated uv and texture density at the barycentrics `b`.
+    fn surface(&self, b: [f32; 2]) -> ([f32; 2], f32) {
+        let uv = match self.uv {
+            Some(uv) => uv,
+            None => return (b, 0.),
+        };
+
+        let w = 1. - b[0] - b[1];
+        let at = [0, 1].map(|i| uv[0][i] * w + uv[1][i] * b[0] + uv[2][i] * b[1]);
+
+        let e1 = [uv[1][0] - uv[0][0], uv[1][1] - uv[0][1]];
+        let e2 = [uv[2][0] - uv[0][0], uv[2][1] - uv[0][1]];
+        let uv_area = (e1[0] * e2[1] - e1[1] * e2[0]).abs();
+        let area = self.area();
+        let density = if area > 0. {
+            (0.5 * uv_area / area).sqrt()
+        } else {
+            0.
+        };
+
+        (at, density)
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.v.iter().fold(Aabb::empty(), |b, v| b.grow(*v))
+    }
+
+    fn area(&self) -> f32 {
+        let c = cross(diff(self.v[1], self.v[0]), diff(self.v[2], self.v[0]));
+        0.5 * dot(c, c).sqrt()
+    }
+
+    fn normal(&self) -> [f32; 3] {
+        unit(cross(
+            diff(self.v[1], self.v[0]),
+            diff(self.v[2], self.v[0]),
+        ))
+    }
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene {
+            spheres: vec![],
+            triangles: vec![],
+            lights: vec![],
+            materials: vec![],
+            environment: Environment::default(),
+            motions: vec![],
+            objects: vec![],
+            meshes: vec![],
+            instances: vec![],
+            media: vec![],
+            fog: None,
+            emitters: OnceLock::new(),
+            instance_bvh: OnceLock::new(),
+        }
+    }
+
+    /// Collects the faces of every object of the mesh, already in world
+    /// space. They are placed once with the identity so hierarchies find
+    /// them, except emissive ones which light sampling needs as triangles.
+    pub fn from_mesh(mesh: &Mesh) -> Scene {
+        let mut scene = Scene::new();
+        for (name, obj) in mesh.objects.iter() {
+            if obj.material.is_emissive() {
+                scene.push_object(name, obj, None);
+            } else {
+                let mesh = scene.push_mesh(obj);
+                scene.push_instance(name, mesh, Mat4x4::unit(), [1., 1., 1.]);
+            }
+        }
+        scene
+    }
+
+    /// Places every object of the graph at its nodes. Objects are shared by
+    /// their instances, except emissive ones which are copied to world space
+    /// so light sampling finds them.
+    pub fn from_graph(graph: &SceneGraph) -> Scene {
+        let mut scene = Scene::new();
+        let mut meshes: Vec<Option<usize>> = vec![None; graph.objects().len()];
+        for id in graph.traverse() {
+            let node = graph.node(id);
+            let object = match node.object {
+                Some(object) => object,
+                None => continue,
+            };
+            let obj = graph.object(object);
+            let name = graph.path(id);
+
+            if obj.material.is_emissive() {
+                let mut world = obj.clone();
+                world.transform(&node.world());
+                scene.push_object(&name, &world, None);
+                continue;
+            }
+
+            let mesh = match meshes[object] {
+                Some(mesh) => mesh,
+                None => {
+                    let mesh = scene.push_mesh(obj);
+                    meshes[object] = Some(mesh);
+                    mesh
+                }
+            };
+            scene.push_instance(&name, mesh, node.world(), node.color);
+        }
+        scene
+    }
+
+    /// Adds the faces of an object, in its own space, as a mesh to instance.
+    pub fn push_mesh(&mut self, obj: &Obj3D) -> usize {
+        let material = self.push_material(obj.material.clone());
+        self.meshes.push(Arc::new(InstanceMesh::new(obj, material)));
+        self.meshes.len() - 1
+    }
+
+    /// Places a mesh with the `to_world` transform and albedo `tint`.
+    /// Emissive instances only light the scene when hit by chance.
+    pub fn push_instance(&mut self, name: &str, mesh: usize, to_world: Mat4x4, tint: [f32; 3]) {
+        let object = self.object_id(name);
+        self.instances.push(Instance {
+            mesh,
+            to_world,
+            to_local: to_world.inverse().unwrap_or(Mat4x4::unit()),
+            tint,
+            object,
+        });
+    }
+
+    fn instance_bvh(&self) -> &Bvh {
+        self.instance_bvh.get_or_init(|| {
+            let boxes: Vec<Aabb> = self
+                .instances
+                .iter()
+                .map(|i| self.meshes[i.mesh].bounds().transform(&i.to_world))
+                .collect();
+            Bvh::build(&boxes)
+        })
+    }
+
+    /// World bounds of the static triangles and of the instances.
+    pub fn bounds(&self) -> Aabb {
+        let triangles = self
+            .triangles
+            .iter()
+            .filter(|t| t.motion.is_none())
+            .fold(Aabb::empty(), |b, t| b.union(t.bounds()));
+        self.instance_bvh().bounds().union(triangles)
+    }
+
+    /// Adds the faces of an object with its material. A moving object keeps
+    /// its vertexes in object space and is placed by the motion.
+    pub fn push_object(&mut self, name: &str, obj: &Obj3D, motion: Option<Motion>) {
+        let material = self.push_material(obj.material.clone());
+        let motion = motion.map(|m| self.push_motion(m));
+        let object = self.object_id(name);
+
+        for face in obj.faces.iter() {
+            self.triangles.push(Triangle {
+                v: face.vertexes.map(|i| obj.vertexes[i].to_vec_3()),
+                uv: face.uvs,
+                material,
+                motion,
+                object,
+            });
+        }
+    }
+
+    /// Index of the object called `name`, added if it is new.
+    pub fn object_id(&mut self, name: &str) -> usize {
+        match self.objects.iter().position(|o| o == name) {
+            Some(i) => i,
+            None => {
+                self.objects.push(name.to_string());
+                self.objects.len() - 1
+            }
+        }
+    }
+
+    pub fn push_motion(&mut self, motion: Motion) -> usize {
+        self.motions.push(motion);
+        self.motions.len() - 1
+    }
+
+    /// Adds a material and returns its index.
+    pub fn push_material(&mut self, material: Material) -> usize {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    pub fn intersect(&self, ray: &Ray, t_max: f32) -> Option<Hit> {
+        let local = self.localize(ray);
+        let ray_for = |motion: Option<usize>| match motion {
+            Some(m) => local[m].as_ref().map(|(r, _)| r),
+            None => Some(ray),
+        };
+
+        let mut closest = t_max;
+        let mut hit = None;
+        let mut index = None;
+
+        for sphere in self.spheres.iter() {
+            let r = match ray_for(sphere.motion) {
+                Some(r) => r,
+                None => continue,
+            };
+            if let Some(t) = sphere.intersect(r, closest) {
+                closest = t;
+                let normal = unit(diff(r.at(t), sphere.center));
+                let (uv, density) = sphere.surface(normal);
+                let owner = (sphere.material, sphere.motion, sphere.object);
+                hit = Some((normal, uv, density, owner, WHITE));
+                index = None;
+            }
+        }
+
+        for (i, triangle) in self.triangles.iter().enumerate() {
+            let r = match ray_for(triangle.motion) {
+                Some(r) => r,
+                None => continue,
+            };
+            if let Some((t, b)) = triangle.intersect(r, closest) {
+                closest = t;
+                let (uv, density) = triangle.surface(b);
+                let owner = (triangle.material, triangle.motion, triangle.object);
+                hit = Some((triangle.normal(), uv, density, owner, WHITE));
+                index = Some(i);
+            }
+        }
+
+        let mut nearest = None;
+        self.instance_bvh().traverse(ray, closest, |i, t_max| {
+            let (t, k, b) = self.intersect_instance(&self.instances[i], ray, t_max)?;
+            nearest = Some((t, i, k, b));
+            Some(t)
+        });
+        if let Some((t, i, k, b)) = nearest {
+            let instance = &self.instances[i];
+            let triangle = &self.meshes[instance.mesh].triangles[k];
+            closest = t;
+
+            let to_world = instance.to_local.transpose();
+            let normal = unit(to_world.mul_dir(triangle.normal()));
+            let (uv, density) = triangle.surface(b);
+            // texture density per world unit of the scaled triangle
+            let e1 = instance
+                .to_world
+                .mul_dir(diff(triangle.v[1], triangle.v[0]));
+            let e2 = instance
+                .to_world
+                .mul_dir(diff(triangle.v[2], triangle.v[0]));
+            let c = cross(e1, e2);
+            let area = 0.5 * dot(c, c).sqrt();
+            let density = if area > 0. {
+                density * (triangle.area() / area).sqrt()
+            } else {
+                0.
+            };
+
+            let owner = (triangle.material, None, instance.object);
+            hit = Some((normal, uv, density, owner, instance.tint));
+            index = None;
+        }
+
+        hit.map(
+            |(normal, uv, uv_density, (material, motion, object), tint)| {
+                let normal = match motion.and_then(|m| local[m].as_ref()) {
+                    Some((_, to_world)) => unit(to_world.mul_dir(normal)),
+                    None => normal,
+                };
+                let front_face = dot(normal, ray.dir) < 0.0;
+                Hit {
+                    point: ray.at(closest),
+                    normal: if front_face { normal } else { neg(normal) },
+                    front_face,
+                    material,
+                    t: closest,
+                    uv,
+                    uv_density,
+                    triangle: index,
+                    time: ray.time,
+                    object,
+                    motion,
+                    tint,
+                }
+            },
+        )
+    }
+
+    /// Closest triangle of the instance mesh before `t_max`, with its
+    /// distance and barycentrics.
+    fn intersect_instance(
+        &self,
+        instance: &Instance,
+        ray: &Ray,
+        t_max: f32,
+    ) -> Option<(f32, usize, [f32; 2])> {
+        let local = instance.localize(ray);
+        let mesh = &self.meshes[instance.mesh];
+        let mut nearest = None;
+        mesh.bvh.traverse(&local, t_max, |k, t_max| {
+            let (t, b) = mesh.triangles[k].intersect(&local, t_max)?;
+            nearest = Some((t, k, b));
+            Some(t)
+        });
+        nearest
+    }
+
+    /// Albedo at a hit, textured and tinted.
+    pub fn albedo(&self, hit: &Hit, footprint: f32) -> [f32; 3] {
+        let material = &self.materials[hit.material];
+        comp_dot(material.albedo_at(hit.point, hit.uv, footprint), hit.tint)
+    }
+
+    /// The ray in the object space of every motion.
+    fn localize(&self, ray: &Ray) -> Vec<Option<(Ray, Mat4x4)>> {
+        self.motions.iter().map(|m| m.localize(ray)).collect()
+    }
+
+    /// Closest area light the ray reaches before `t_max`.
+    pub fn intersect_light(&self, ray: &Ray, t_max: f32) -> Option<(usize, f32)> {
+        let mut closest = None;
+        for (i, light) in self.lights.iter().enumerate() {
+            let limit = closest.map(|(_, t)| t).unwrap_or(t_max);
+            if let Some(t) = light.intersect(ray.origin, ray.dir, limit) {
+                closest = Some((i, t));
+            }
+        }
+        closest
+    }
+
+    fn emitters(&self) -> &Emitters {
+        self.emitters.get_or_init(|| {
+            let mut emitters = Emitters {
+                triangles: vec![],
+                cdf: vec![],
+                area: 0.,
+            };
+            for (i, t) in self.triangles.iter().enumerate() {
+                // moving emitters are only found by BSDF sampling
+                if t.motion.is_none() && self.materials[t.material].is_emissive() {
+                    emitters.area += t.area();
+                    emitters.triangles.push(i);
+                    emitters.cdf.push(emitters.area);
+                }
+            }
+            emitters
+        })
+    }
+
+    /// Samples a point on the emissive triangles as seen from `p`.
+    pub fn sample_emitter(&self, p: [f32; 3], u: [f32; 3]) -> Option<LightSample> {
+        let emitters = self.emitters();
+        if emitters.triangles.is_empty() {
+            return None;
+        }
+
+        let target = u[0] * emitters.area;
+        let k = emitters
+            .cdf
+            .partition_point(|c| *c <= target)
+            .min(emitters.triangles.len() - 1);
+        let triangle = &self.triangles[emitters.triangles[k]];
+
+        // uniform point on the triangle
+        let su = u[1].sqrt();
+        let (b1, b2) = (1. - su, u[2] * su);
+        let q = add(
+            triangle.v[0],
+            add(
+                mul(diff(triangle.v[1], triangle.v[0]), b1),
+                mul(diff(triangle.v[2], triangle.v[0]), b2),
+            ),
+        );
+
+        let d = diff(q, p);
+        let dist2 = dot(d, d);
+        let wi = unit(d);
+        // emitters light the side their normal points to
+        let cos = -dot(wi, triangle.normal());
+        if cos <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            wi,
+            dist: dist2.sqrt(),
+            radiance: self.materials[triangle.material].emission,
+            pdf: dist2 / (cos * emitters.area),
+        })
+    }
+
+    /// Density of `sample_emitter` choosing the point `hit` from a ray along `dir`.
+    pub fn emitter_pdf(&self, hit: &Hit, dir: [f32; 3]) -> f32 {
+        match hit.triangle {
+            Some(i)
+                if self.triangles[i].motion.is_none()
+                    && self.materials[hit.material].is_emissive() =>
+            {
+                let cos = dot(hit.normal, dir).abs();
+                hit.t * hit.t / (cos * self.emitters().area)
+            }
+            _ => 0.,
+        }
+    }
+
+    /// True when anything blocks the ray before `t_max`.
+    pub fn occluded(&self, ray: &Ray, t_max: f32) -> bool {
+        let local = self.localize(ray);
+        let ray_for = |motion: Option<usize>| match motion {
+            Some(m) => local[m].as_ref().map(|(r, _)| r),
+            None => Some(ray),
+        };
+
+        self.spheres.iter().any(|s| {
+            ray_for(s.motion)
+                .and_then(|r| s.intersect(r, t_max))
+                .is_some()
+        }) || self.triangles.iter().any(|tr| {
+            ray_for(tr.motion)
+                .and_then(|r| tr.intersect(r, t_max))
+                .is_some()
+        }) || self.instance_bvh().any(ray, t_max, |i| {
+            let instance = &self.instances[i];
+            let local = instance.localize(ray);
+            let mesh = &self.meshes[instance.mesh];
+            mesh.bvh.any(&local, t_max, |k| {
+                mesh.triangles[k].intersect(&local, t_max).is_some()
+            })
+        })
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Scene {
+        Scene::new()
+    }
+}
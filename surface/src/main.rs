@@ -1,21 +1,42 @@
-use common::model::figure::*;
 use common::model::mat::*;
+use common::model::raster::Raster;
 
-use crate::noise::{NoiseFn, Perlin};
 use nannou::color::*;
 use nannou::event::WindowEvent::*;
 use nannou::event::*;
 use nannou::*;
+use options::{Options, Style};
+use wgpu::Texture;
+use world::World;
+
+mod options;
+mod render;
+mod world;
 
 struct Model {
-    eye: Vertex,
     camera: Mat4x4,
     perspective_proj: Mat4x4,
-    zoff: f32,
-    xoff: f32,
+    /// Filled faces of the frame, shown in the faces style.
+    texture: Texture,
+    world: World,
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = Options::parse(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    // `surface --out frame.png` renders without a window
+    if options.out.is_some() {
+        if let Err(e) = render::run(options) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     //nannou::sketch(view).run();
     nannou::app(model).update(update).run();
 }
@@ -24,12 +45,25 @@ fn model(app: &App) -> Model {
     app.new_window().event(event).view(view).build().unwrap();
     let viewport = app.window_rect();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let world = Options::parse(&args)
+        .and_then(World::new)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+
+    let texture = wgpu::TextureBuilder::new()
+        .size([viewport.w() as u32, viewport.h() as u32])
+        .format(wgpu::TextureFormat::Rgba8Unorm)
+        .usage(wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING)
+        .build(app.main_window().device());
+
     Model {
-        eye: Vertex::from_vec([0., 1., -2.]),
-        camera: viewer([0., 1., -2.], [0., 0.5, 0.], [0., 1.2, 0.]),
-        perspective_proj: perspective_projection(90., viewport.w() / viewport.h(), -10., -1.),
-        zoff: 0.0,
-        xoff: 0.0,
+        camera: viewer(world.eye, world.at, world.up),
+        perspective_proj: perspective_projection(world.fov, viewport.w() / viewport.h(), -10., -1.),
+        texture,
+        world,
     }
 }
 
@@ -39,21 +73,23 @@ fn event(_app: &App, model: &mut Model, event: WindowEvent) {
             println!("Pressed {:?}", key);
         }
 
+        KeyPressed(Key::S) => {
+            if let Err(e) = model.world.save() {
+                eprintln!("{}", e);
+            }
+        }
+
         Resized(dim) => {
-            model.perspective_proj = perspective_projection(60., dim[0] / dim[1], -10., -1.)
+            model.perspective_proj =
+                perspective_projection(model.world.fov, dim[0] / dim[1], -10., -1.)
         }
 
         _ => {}
     }
 }
 
-fn update(_app: &App, model: &mut Model, _update: Update) {
-    model.zoff += 0.1;
-    //model.xoff -= 0.08;
-}
-
-fn map(x: f32, in_min: f32, in_max: f32, out_min: f32, out_max: f32) -> f32 {
-    (x - in_min) * (out_max - out_min) / (in_max - in_min) + out_min
+fn update(_app: &App, model: &mut Model, update: Update) {
+    model.world.update(update.since_start.as_secs_f32());
 }
 
 fn view(app: &App, model: &Model, frame: Frame) {
@@ -64,47 +100,26 @@ fn view(app: &App, model: &Model, frame: Frame) {
     draw.background().color(WHITE);
 
     let viewport = app.window_rect();
-
-    let perlin = Perlin::new();
-
-    let len = 60;
-
-    let mut zoff = model.zoff;
-
-    let init_x = model.xoff;
-
-    let mut local = Mesh::new();
-
-    let surface = local.push_object("fabric");
-
-    for z in 0..len {
-        let mut xoff = init_x;
-        for x in 0..len {
-            let noise = perlin.get([zoff as f64, xoff as f64]) as f32;
-
-            let height = map(noise, -1., 1., -0.08, 0.08);
-
-            let v = Vertex::from([x as f32 * 0.05, height, z as f32 * 0.05, 1.]);
-            surface.push_vertex(v);
-
-            if x < len - 1 && z < len - 1 {
-                surface.push_edge(Edge::new(z * len + x, z * len + x + 1));
-                surface.push_edge(Edge::new(z * len + x, (z + 1) * len + x));
-                surface.push_edge(Edge::new(z * len + x + 1, (z + 1) * len + x));
-            }
-            xoff += 0.09;
+    let view_proj = model.perspective_proj * model.camera;
+
+    match model.world.options.style {
+        Style::Faces => {
+            let [w, h] = model.texture.size();
+            let mut raster = Raster::new(w as usize, h as usize);
+            model.world.draw_faces(&mut raster, view_proj);
+
+            let image = raster.to_image();
+            model.texture.upload_data(
+                app.main_window().device(),
+                &mut frame.command_encoder(),
+                image.as_flat_samples().as_slice(),
+            );
+            draw.texture(&model.texture);
         }
-        zoff += 0.1;
+        Style::Lines => model
+            .world
+            .draw_lines(&draw, view_proj, viewport.w(), viewport.h()),
     }
 
-    let transform = translation_mat(-1., 0., 0.);
-
-    let mat = (model.perspective_proj * model.camera * transform * &local).to_screen(
-        viewport.w() as f32,
-        viewport.h() as f32,
-    );
-
-    mat.draw_lines(&draw);
-
     draw.to_frame(app, &frame).unwrap();
 }
@@ -0,0 +1,304 @@
+use std::str::FromStr;
+
+use common::terrain::chunks::Chunks;
+use common::terrain::coloring::Coloring;
+use common::terrain::erosion::{Hydraulic, Thermal};
+use common::terrain::fractal::{load_recipe, BaseNoise, Noise, Recipe};
+use common::terrain::heightfield::Heightfield;
+use common::terrain::heightmap::{load_heightmap, save_heightmap, Depth};
+use common::terrain::water::{load_water, Water};
+use noise::NoiseFn;
+
+/// How the window shows the terrain and the scene objects.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Style {
+    /// Filled faces, the terrain colored by height and slope.
+    Faces,
+    /// Outlines over a white background.
+    Lines,
+}
+
+/// Settings of `surface`.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Scene file whose camera and objects are shown over the terrain.
+    pub scene: Option<String>,
+    /// Samples along each side of the terrain.
+    pub resolution: usize,
+    /// Length of each side.
+    pub extent: f32,
+    /// Largest height above or below 0.
+    pub amplitude: f32,
+    /// Noise features per unit.
+    pub frequency: f32,
+    /// Single octave noise used without a recipe.
+    pub noise: BaseNoise,
+    pub seed: u32,
+    /// Noise recipe file, reloaded whenever it changes.
+    pub recipe: Option<String>,
+    /// Distance the terrain scrolls towards -z each frame. Heightmaps and
+    /// eroded terrain stay in place.
+    pub speed: f32,
+    /// Hydraulic erosion droplets, seeded with `seed`.
+    pub droplets: usize,
+    /// Thermal erosion iterations.
+    pub thermal: usize,
+    /// PNG or `.r32` heightmap shown instead of the noise, reloaded whenever
+    /// it changes.
+    pub heightmap: Option<String>,
+    /// PNG or `.r32` file the terrain is written to at start and on `S`.
+    pub save_heightmap: Option<String>,
+    /// Height of heightmap value 1, `2 * amplitude` if not given.
+    pub height_scale: Option<f32>,
+    /// Height of heightmap value 0, `-amplitude` if not given.
+    pub height_base: Option<f32>,
+    /// Bits per pixel of saved PNG heightmaps.
+    pub bits: Depth,
+    /// Length of each side of a chunk of noise terrain.
+    pub chunk_size: f32,
+    /// Samples along each side of the nearest chunks.
+    pub chunk_resolution: usize,
+    /// Chunks shown on each side of the one under the camera.
+    pub chunk_radius: usize,
+    /// Levels of detail, each halving the samples of the one before.
+    pub lods: usize,
+    /// Distance up to which chunks are shown in full detail.
+    pub lod_distance: f32,
+    pub style: Style,
+    /// Water file whose waves move over the terrain.
+    pub water: Option<String>,
+    /// Time the first frame is shown at.
+    pub time: f32,
+    /// Image a single frame is rendered to without opening a window.
+    pub out: Option<String>,
+    /// Size of the rendered image.
+    pub width: usize,
+    pub height: usize,
+}
+
+pub const USAGE: &str = "usage: surface [scene.json] [--resolution N] [--extent E] \
+[--amplitude A] [--frequency F] [--noise perlin|simplex|open-simplex|super-simplex|value|worley] \
+[--seed N] [--recipe recipe.json] [--speed S] [--droplets N] [--thermal N] \
+[--heightmap map.png|map.r32] [--save-heightmap map.png|map.r32] [--height-scale S] \
+[--height-base B] [--bits 8|16] [--chunk-size S] [--chunk-resolution N] [--chunk-radius N] \
+[--lods N] [--lod-distance D] [--style faces|lines] [--water water.json] [--time T] [--out frame.png] [--width N] [--height N]
+
+Noise terrain without erosion is shown in chunks around the camera; otherwise a single field of \
+--resolution samples over --extent is, which does not scroll. --out renders the filled faces of the first frame to an \
+image of --width by --height without opening a window.";
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            scene: None,
+            resolution: 60,
+            extent: 2.95,
+            amplitude: 0.08,
+            frequency: 2.,
+            noise: BaseNoise::Perlin,
+            seed: 0,
+            recipe: None,
+            speed: 0.05,
+            droplets: 0,
+            thermal: 0,
+            heightmap: None,
+            save_heightmap: None,
+            height_scale: None,
+            height_base: None,
+            bits: Depth::Sixteen,
+            chunk_size: 1.,
+            chunk_resolution: 33,
+            chunk_radius: 3,
+            lods: 4,
+            lod_distance: 1.5,
+            style: Style::Faces,
+            water: None,
+            time: 0.,
+            out: None,
+            width: 640,
+            height: 480,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                if options.scene.is_some() {
+                    return Err(format!("unexpected argument {}\n{}", arg, USAGE));
+                }
+                options.scene = Some(arg.clone());
+                continue;
+            }
+
+            let value = args
+                .next()
+                .ok_or(format!("missing value for {}\n{}", arg, USAGE))?;
+
+            match arg.as_str() {
+                "--resolution" => options.resolution = parse(arg, value)?,
+                "--extent" => options.extent = parse(arg, value)?,
+                "--amplitude" => options.amplitude = parse(arg, value)?,
+                "--frequency" => options.frequency = parse(arg, value)?,
+                "--noise" => options.noise = parse(arg, value)?,
+                "--seed" => options.seed = parse(arg, value)?,
+                "--recipe" => options.recipe = Some(value.clone()),
+                "--speed" => options.speed = parse(arg, value)?,
+                "--droplets" => options.droplets = parse(arg, value)?,
+                "--thermal" => options.thermal = parse(arg, value)?,
+                "--heightmap" => options.heightmap = Some(value.clone()),
+                "--save-heightmap" => options.save_heightmap = Some(value.clone()),
+                "--height-scale" => options.height_scale = Some(parse(arg, value)?),
+                "--height-base" => options.height_base = Some(parse(arg, value)?),
+                "--bits" => {
+                    options.bits = match value.as_str() {
+                        "8" => Depth::Eight,
+                        "16" => Depth::Sixteen,
+                        _ => return Err(format!("invalid value {} for {}", value, arg)),
+                    }
+                }
+                "--chunk-size" => options.chunk_size = parse(arg, value)?,
+                "--chunk-resolution" => options.chunk_resolution = parse(arg, value)?,
+                "--chunk-radius" => options.chunk_radius = parse(arg, value)?,
+                "--lods" => options.lods = parse(arg, value)?,
+                "--lod-distance" => options.lod_distance = parse(arg, value)?,
+                "--style" => {
+                    options.style = match value.as_str() {
+                        "faces" => Style::Faces,
+                        "lines" => Style::Lines,
+                        _ => return Err(format!("invalid value {} for {}", value, arg)),
+                    }
+                }
+                "--water" => options.water = Some(value.clone()),
+                "--time" => options.time = parse(arg, value)?,
+                "--out" => options.out = Some(value.clone()),
+                "--width" => options.width = parse(arg, value)?,
+                "--height" => options.height = parse(arg, value)?,
+                _ => return Err(format!("unknown option {}\n{}", arg, USAGE)),
+            }
+        }
+
+        if options.resolution < 2 || options.extent <= 0. {
+            return Err(String::from(
+                "resolution must be at least 2 and extent positive",
+            ));
+        }
+        if options.chunk_resolution < 2 || options.chunk_size <= 0. || options.lods == 0 {
+            return Err(String::from(
+                "chunk resolution must be at least 2, chunk size positive and lods at least 1",
+            ));
+        }
+        if options.width == 0 || options.height == 0 {
+            return Err(String::from("width and height must be positive"));
+        }
+        if options.height_scale == Some(0.) {
+            return Err(String::from("height scale must not be 0"));
+        }
+
+        Ok(options)
+    }
+
+    /// The heightmap if given, `extent` wide and as deep as keeps its cells
+    /// square, otherwise the noise moved by `offset`, eroded if asked for.
+    pub fn terrain(&self, noise: &Noise, offset: [f32; 2]) -> Result<Heightfield, String> {
+        let (scale, base) = self.vertical();
+        let mut terrain = match &self.heightmap {
+            Some(path) => {
+                let mut field = load_heightmap(path, [self.extent; 2], scale, base)
+                    .map_err(|e| format!("{}: {}", path, e))?;
+                let [w, h] = field.resolution;
+                field.extent[1] = self.extent * (h - 1) as f32 / (w - 1) as f32;
+                field
+            }
+            None => Heightfield::from_noise(
+                [self.resolution; 2],
+                [self.extent; 2],
+                noise,
+                self.frequency,
+                self.amplitude,
+                offset,
+            ),
+        };
+        if self.droplets > 0 {
+            Hydraulic::new(self.droplets, self.seed as u64).apply(&mut terrain);
+        }
+        if self.thermal > 0 {
+            Thermal::new(self.thermal).apply(&mut terrain);
+        }
+        Ok(terrain)
+    }
+
+    /// Whether the terrain is shown in chunks, which erosion and heightmaps
+    /// need whole.
+    pub fn chunked(&self) -> bool {
+        self.heightmap.is_none() && self.droplets == 0 && self.thermal == 0
+    }
+
+    /// Empty chunk cache, generating a few chunks per frame.
+    pub fn chunks(&self) -> Chunks {
+        Chunks::new(self.chunk_size, self.chunk_resolution)
+            .with_radius(self.chunk_radius)
+            .with_lods(self.lods, self.lod_distance)
+            .with_skirt(self.amplitude)
+            .with_budget(8)
+            .with_coloring(self.coloring())
+    }
+
+    /// Bands spread over the heights heightmaps span.
+    pub fn coloring(&self) -> Coloring {
+        let (scale, base) = self.vertical();
+        Coloring::new(base, base + scale)
+    }
+
+    /// Height of the noise terrain at an x, z position.
+    pub fn height(&self, noise: &Noise, x: f32, z: f32) -> f32 {
+        let p = [(x * self.frequency) as f64, (z * self.frequency) as f64];
+        self.amplitude * noise.get(p) as f32
+    }
+
+    /// The waves of the water file if given.
+    pub fn water(&self) -> Result<Option<Water>, String> {
+        match &self.water {
+            Some(path) => load_water(path)
+                .map(Some)
+                .map_err(|e| format!("{}: {}", path, e)),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes the terrain to the `save_heightmap` file if given.
+    pub fn save(&self, terrain: &Heightfield) -> Result<(), String> {
+        let (scale, base) = self.vertical();
+        match &self.save_heightmap {
+            Some(path) => save_heightmap(terrain, path, scale, base, self.bits)
+                .map_err(|e| format!("{}: {}", path, e)),
+            None => Ok(()),
+        }
+    }
+
+    /// File the terrain comes from, watched for changes.
+    pub fn source(&self) -> Option<&String> {
+        self.heightmap.as_ref().or(self.recipe.as_ref())
+    }
+
+    /// Height between heightmap values 0 and 1 and height of value 0, by
+    /// default spanning the noise range.
+    fn vertical(&self) -> (f32, f32) {
+        (
+            self.height_scale.unwrap_or(2. * self.amplitude),
+            self.height_base.unwrap_or(-self.amplitude),
+        )
+    }
+
+    /// The recipe file if given, otherwise `noise` alone.
+    pub fn recipe(&self) -> Result<Recipe, String> {
+        match &self.recipe {
+            Some(path) => load_recipe(path).map_err(|e| format!("{}: {}", path, e)),
+            None => Ok(Recipe::new(self.noise, self.seed)),
+        }
+    }
+}
+
+fn parse<T: FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {} for {}", value, arg))
+}
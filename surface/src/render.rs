@@ -0,0 +1,37 @@
+use std::time::Instant;
+
+use common::model::mat::*;
+use common::model::raster::Raster;
+
+use crate::options::Options;
+use crate::world::World;
+
+/// Renders the filled faces of the first frame without opening a window.
+pub fn run(options: Options) -> Result<(), String> {
+    let out = options
+        .out
+        .clone()
+        .unwrap_or_else(|| String::from("frame.png"));
+    let (width, height) = (options.width, options.height);
+
+    let start = Instant::now();
+    let world = World::new(options)?;
+    let camera = viewer(world.eye, world.at, world.up);
+    let projection = perspective_projection(world.fov, width as f32 / height as f32, -10., -1.);
+
+    let mut raster = Raster::new(width, height);
+    world.draw_faces(&mut raster, projection * camera);
+    raster
+        .to_image()
+        .save(&out)
+        .map_err(|e| format!("{}: {}", out, e))?;
+
+    eprintln!(
+        "Rendered {}x{} to {} in {:.2}s",
+        width,
+        height,
+        out,
+        start.elapsed().as_secs_f32()
+    );
+    Ok(())
+}
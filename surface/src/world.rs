@@ -0,0 +1,296 @@
+use std::fs;
+use std::time::SystemTime;
+
+use common::model::environment::Environment;
+use common::model::figure::*;
+use common::model::graph::SceneGraph;
+use common::model::mat::*;
+use common::model::raster::Raster;
+use common::model::scene::load_scene;
+use common::terrain::chunks::Chunks;
+use common::terrain::coloring::Coloring;
+use common::terrain::fractal::Noise;
+use common::terrain::heightfield::Heightfield;
+use common::terrain::water::Water;
+use nannou::Draw;
+
+use crate::options::Options;
+
+/// Direction of the light shading the filled faces.
+const LIGHT: [f32; 3] = [-1., -1., 0.5];
+
+/// The terrain, the scene objects over it and the camera looking at them.
+pub struct World {
+    pub eye: [f32; 3],
+    pub at: [f32; 3],
+    pub up: [f32; 3],
+    /// Vertical field of view in degrees.
+    pub fov: f32,
+    /// Background of the filled faces.
+    pub sky: Environment,
+    pub options: Options,
+    /// Objects of the scene file, outlined over the fabric, and the node
+    /// placing the fabric.
+    graph: SceneGraph,
+    fabric: usize,
+    noise: Noise,
+    /// Modification time of the heightmap or recipe file the terrain was
+    /// built from.
+    source_modified: Option<SystemTime>,
+    /// Distance the terrain has scrolled by.
+    zoff: f32,
+    xoff: f32,
+    /// Rebuilt when its source changes, unless the terrain is chunked.
+    terrain: Heightfield,
+    /// Colored faces of `terrain`, rebuilt with it.
+    painted: Obj3D,
+    chunks: Option<Chunks>,
+    coloring: Coloring,
+    /// Layered over the terrain, moving with it.
+    water: Option<Water>,
+    /// Time the waves are shown at.
+    time: f32,
+}
+
+impl World {
+    /// The first frame, chunks all generated.
+    pub fn new(options: Options) -> Result<World, String> {
+        let noise = options.recipe()?.build();
+        let water = options.water()?;
+        let source_modified = options.source().and_then(|path| modified(path));
+        let terrain = options.terrain(&noise, [0., 0.])?;
+        if let Err(e) = options.save(&terrain) {
+            eprintln!("{}", e);
+        }
+
+        // `surface scene.json` looks through the scene camera at its objects
+        let (eye, at, up, fov, sky, mut graph) = match options.scene.clone() {
+            Some(path) => {
+                let file = load_scene(&path).map_err(|e| format!("{}: {}", path, e))?;
+                let c = file.camera;
+                (c.eye, c.at, c.up, c.fov, file.environment, file.graph)
+            }
+            None => {
+                let (eye, at, up) = ([0., 1., -2.], [0., 0.5, 0.], [0., 1.2, 0.]);
+                (eye, at, up, 90., Environment::sky(), SceneGraph::new())
+            }
+        };
+        for id in 0..graph.objects().len() {
+            wireframe(graph.object_mut(id));
+        }
+        let fabric = graph.push_node("fabric", None, translation_mat(-1., 0., 0.), None);
+        let chunks = options.chunked().then(|| options.chunks());
+        let coloring = options.coloring();
+        let painted = painted(&terrain, &coloring);
+        let time = options.time;
+
+        let mut world = World {
+            eye,
+            at,
+            up,
+            fov,
+            sky,
+            options,
+            graph,
+            fabric,
+            noise,
+            source_modified,
+            zoff: 0.0,
+            xoff: 0.0,
+            terrain,
+            painted,
+            chunks,
+            coloring,
+            water,
+            time,
+        };
+        if let Some(center) = world.chunk_center() {
+            let (options, noise) = (&world.options, &world.noise);
+            if let Some(chunks) = &mut world.chunks {
+                chunks.fill(center, |x, z| options.height(noise, x, z));
+            }
+        }
+        Ok(world)
+    }
+
+    /// Scrolls the chunks, picks up changes of the terrain source file and
+    /// moves the waves `elapsed` past the first frame.
+    pub fn update(&mut self, elapsed: f32) {
+        self.time = self.options.time + elapsed;
+
+        // only chunks scroll, heightmaps stay in place and erosion is far too
+        // slow to rerun on every frame
+        let heightmap = self.options.heightmap.is_some();
+        if self.chunks.is_some() {
+            self.zoff += self.options.speed;
+        }
+        let mut changed = false;
+
+        // edits of the heightmap or recipe file show up live, broken ones keep
+        // the last terrain
+        if let Some(path) = self.options.source() {
+            let time = modified(path);
+            if time != self.source_modified {
+                self.source_modified = time;
+                if heightmap {
+                    changed = true;
+                } else {
+                    match self.options.recipe() {
+                        Ok(recipe) => {
+                            self.noise = recipe.build();
+                            if let Some(chunks) = &mut self.chunks {
+                                chunks.clear();
+                            }
+                            changed = true;
+                        }
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+            }
+        }
+        //self.xoff -= 0.08;
+
+        if let Some(center) = self.chunk_center() {
+            let (options, noise) = (&self.options, &self.noise);
+            if let Some(chunks) = &mut self.chunks {
+                chunks.update(center, |x, z| options.height(noise, x, z));
+            }
+        } else if changed {
+            match self.options.terrain(&self.noise, [self.xoff, self.zoff]) {
+                Ok(terrain) => {
+                    self.painted = painted(&terrain, &self.coloring);
+                    self.terrain = terrain;
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+
+    /// Writes the terrain to the heightmap file of the options, chunks as
+    /// the single field at the same place.
+    pub fn save(&self) -> Result<(), String> {
+        match self.chunks {
+            Some(_) => {
+                let terrain = self.options.terrain(&self.noise, [self.xoff, self.zoff])?;
+                self.options.save(&terrain)
+            }
+            None => self.options.save(&self.terrain),
+        }
+    }
+
+    /// Fills the terrain, the water and the scene objects, then the sky
+    /// around them.
+    pub fn draw_faces(&self, raster: &mut Raster, view_proj: Mat4x4) {
+        self.surface_pieces(|obj, transform| {
+            raster.draw_instances(
+                obj,
+                &[Instance::new(transform)],
+                view_proj,
+                view_proj,
+                LIGHT,
+                0,
+            );
+        });
+
+        let instances = self.graph.instances();
+        for (id, obj) in self.graph.objects().iter().enumerate() {
+            raster.draw_instances(obj, &instances[id], view_proj, view_proj, LIGHT, id + 1);
+        }
+        raster.draw_environment(&self.sky, self.eye, self.at, self.up, self.fov);
+    }
+
+    /// Outlines the terrain, the water and the scene objects.
+    pub fn draw_lines(&self, draw: &Draw, view_proj: Mat4x4, width: f32, height: f32) {
+        self.surface_pieces(|obj, transform| {
+            obj.draw_instance_lines(draw, &[Instance::new(transform)], view_proj, width, height);
+        });
+
+        // shared objects are projected per placement, not copied
+        for (id, instances) in self.graph.instances().iter().enumerate() {
+            self.graph
+                .object(id)
+                .draw_instance_lines(draw, instances, view_proj, width, height);
+        }
+    }
+
+    /// Calls `f` with each piece of the terrain and its placement, the
+    /// chunks or the single field, then with the water over it.
+    fn surface_pieces(&self, mut f: impl FnMut(&Obj3D, Mat4x4)) {
+        let transform = self.graph.node(self.fabric).world();
+        let place =
+            |[x, z]: [f32; 2]| transform * translation_mat(x - self.xoff, 0., z - self.zoff);
+        match &self.chunks {
+            Some(chunks) => {
+                for chunk in chunks.visible() {
+                    f(&chunk.obj, place(chunk.origin));
+                }
+            }
+            None => f(&self.painted, transform),
+        }
+
+        if let Some(water) = &self.water {
+            let (resolution, extent, origin) = self.water_grid();
+            let obj = water.to_obj(resolution, extent, origin, self.time);
+            f(&obj, place(origin));
+        }
+    }
+
+    /// Samples along x and z, size and start over the noise of the water
+    /// grid: the single field, or the chunks in view at half the detail of
+    /// the nearest ones, on a lattice that stays put as they change.
+    fn water_grid(&self) -> ([usize; 2], [f32; 2], [f32; 2]) {
+        match (&self.chunks, self.chunk_center()) {
+            (Some(chunks), Some(center)) => {
+                let r = chunks.radius as f32;
+                let side = 2. * r + 1.;
+                let start = center.map(|c| ((c / chunks.size).floor() - r) * chunks.size);
+                let per_chunk = ((chunks.resolution - 1) / 2).max(1);
+                (
+                    [side as usize * per_chunk + 1; 2],
+                    [side * chunks.size; 2],
+                    start,
+                )
+            }
+            _ => {
+                // as dense along z as along x over heightmaps of any aspect
+                let [x, z] = self.terrain.extent;
+                let n = self.options.resolution;
+                let depth = ((n - 1) as f32 * z / x).round() as usize + 1;
+                ([n, depth], [x, z], [self.xoff, self.zoff])
+            }
+        }
+    }
+
+    /// The eye over the noise, which it moves across as the terrain
+    /// scrolls, if the terrain is chunked.
+    fn chunk_center(&self) -> Option<[f32; 2]> {
+        self.chunks.as_ref()?;
+        let to_fabric = self.graph.node(self.fabric).world().inverse();
+        let eye = to_fabric.map_or(self.eye, |m| m.mul_point(self.eye));
+        Some([eye[0] + self.xoff, eye[2] + self.zoff])
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Faces of the field colored by `coloring`.
+fn painted(field: &Heightfield, coloring: &Coloring) -> Obj3D {
+    let mut obj = field.to_obj();
+    coloring.paint(field, &mut obj);
+    obj
+}
+
+/// Outlines the triangles of the object with edges.
+fn wireframe(obj: &mut Obj3D) {
+    let edges: Vec<Edge> = obj
+        .faces
+        .iter()
+        .flat_map(|f| {
+            let [a, b, c] = f.vertexes;
+            [Edge::new(a, b), Edge::new(b, c), Edge::new(c, a)]
+        })
+        .collect();
+    obj.push_edges(edges);
+}